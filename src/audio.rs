@@ -0,0 +1,354 @@
+use crate::{Error, Result};
+
+/// Format of PCM samples as described by a WAV header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+/// Linear PCM samples with headers stripped.
+#[derive(Debug, Clone, Copy)]
+pub struct Pcm<'a> {
+    /// Format read from WAV header, `None` for headerless input.
+    pub format: Option<PcmFormat>,
+    pub data: &'a [u8],
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Inspects audio bytes. WAV input is checked to contain 16 bit
+/// PCM and its header is stripped, input without recognizable header
+/// is treated as raw PCM of unknown format.
+pub fn detect_pcm(audio: &[u8]) -> Result<Pcm<'_>> {
+    if audio.starts_with(b"RIFF") {
+        return parse_wav(audio);
+    }
+
+    let container = if audio.starts_with(b"RIFX") {
+        Some("big-endian WAV")
+    } else if audio.starts_with(b"OggS") {
+        Some("OGG")
+    } else if audio.starts_with(b"fLaC") {
+        Some("FLAC")
+    } else if audio.starts_with(b"ID3") {
+        // bare mp3 frame sync is not checked,
+        // since raw samples may start with the same bits
+        Some("MP3")
+    } else if audio.starts_with(b"#!AMR") {
+        Some("AMR")
+    } else {
+        None
+    };
+
+    match container {
+        Some(container) => Err(Error::UnsupportedAudio(format!(
+            "{} audio is not supported, expected WAV or raw 16 bit PCM",
+            container
+        ))),
+        None => Ok(Pcm {
+            format: None,
+            data: audio,
+        }),
+    }
+}
+
+/// Prepends WAV header describing `format` to PCM samples.
+pub fn encode_wav(format: PcmFormat, data: &[u8]) -> Vec<u8> {
+    let block_align = format.channels * format.bits_per_sample / 8;
+    let byte_rate = format.sample_rate * block_align as u32;
+
+    let mut wav = Vec::with_capacity(44 + data.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+    wav.extend_from_slice(&format.channels.to_le_bytes());
+    wav.extend_from_slice(&format.sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&format.bits_per_sample.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(data);
+    wav
+}
+
+fn parse_wav(audio: &[u8]) -> Result<Pcm<'_>> {
+    if audio.len() < 12 || &audio[8..12] != b"WAVE" {
+        return Err(Error::UnsupportedAudio(
+            "RIFF container does not contain WAVE data".to_owned(),
+        ));
+    }
+
+    let mut format = None;
+    let mut position = 12;
+    // walk through chunks until samples are found,
+    // `fmt ` chunk is required to precede `data` chunk
+    while position + 8 <= audio.len() {
+        let id = &audio[position..position + 4];
+        let size = read_u32(audio, position + 4) as usize;
+        let body = position + 8;
+        let end = body.saturating_add(size).min(audio.len());
+
+        match id {
+            b"fmt " => format = Some(parse_fmt(&audio[body..end])?),
+            b"data" => {
+                let format = format.ok_or_else(|| {
+                    Error::UnsupportedAudio("WAV data chunk precedes fmt chunk".to_owned())
+                })?;
+                // streamed WAV files may carry placeholder size of zero
+                // or u32::MAX, so samples are taken up to the end of input
+                let end = if size == 0 { audio.len() } else { end };
+                return Ok(Pcm {
+                    format: Some(format),
+                    data: &audio[body..end],
+                });
+            }
+            _ => {}
+        }
+
+        // chunks are padded to even size
+        position = end + (size & 1);
+    }
+
+    Err(Error::UnsupportedAudio(
+        "WAV audio has no data chunk".to_owned(),
+    ))
+}
+
+fn parse_fmt(chunk: &[u8]) -> Result<PcmFormat> {
+    if chunk.len() < 16 {
+        return Err(Error::UnsupportedAudio(
+            "WAV fmt chunk is too short".to_owned(),
+        ));
+    }
+
+    let mut audio_format = read_u16(chunk, 0);
+    if audio_format == WAVE_FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+        // first two bytes of sub format guid hold the actual format
+        audio_format = read_u16(chunk, 24);
+    }
+    let format = PcmFormat {
+        channels: read_u16(chunk, 2),
+        sample_rate: read_u32(chunk, 4),
+        bits_per_sample: read_u16(chunk, 14),
+    };
+
+    if audio_format != WAVE_FORMAT_PCM {
+        return Err(Error::UnsupportedAudio(format!(
+            "WAV audio format {:#06x} is not supported, expected PCM",
+            audio_format
+        )));
+    }
+    if format.bits_per_sample != 16 {
+        return Err(Error::UnsupportedAudio(format!(
+            "{} bit WAV audio is not supported, expected 16 bit",
+            format.bits_per_sample
+        )));
+    }
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(Error::UnsupportedAudio(
+            "WAV header has zero channels or sample rate".to_owned(),
+        ));
+    }
+
+    Ok(format)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: PcmFormat = PcmFormat {
+        sample_rate: 16000,
+        channels: 1,
+        bits_per_sample: 16,
+    };
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(4 + body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    fn fmt_body(audio_format: u16, bits_per_sample: u16) -> Vec<u8> {
+        let mut body = vec![];
+        body.extend_from_slice(&audio_format.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&16000u32.to_le_bytes());
+        body.extend_from_slice(&32000u32.to_le_bytes());
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&bits_per_sample.to_le_bytes());
+        body
+    }
+
+    fn unsupported(audio: &[u8]) -> String {
+        match detect_pcm(audio) {
+            Err(Error::UnsupportedAudio(message)) => message,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("audio was accepted"),
+        }
+    }
+
+    #[test]
+    fn canonical_header() {
+        let wav = encode_wav(FORMAT, &[1, 2, 3, 4]);
+        assert_eq!(wav.len(), 48);
+
+        let pcm = detect_pcm(&wav).unwrap();
+        assert_eq!(pcm.format, Some(FORMAT));
+        assert_eq!(pcm.data, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn extensible_format() {
+        let mut fmt = fmt_body(WAVE_FORMAT_EXTENSIBLE, 16);
+        // extension size, valid bits, channel mask
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        fmt.extend_from_slice(&4u32.to_le_bytes());
+        // sub format guid, KSDATAFORMAT_SUBTYPE_PCM
+        fmt.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        fmt.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+        ]);
+        let wav = riff(&[chunk(b"fmt ", &fmt), chunk(b"data", &[5, 6])]);
+
+        let pcm = detect_pcm(&wav).unwrap();
+        assert_eq!(pcm.format, Some(FORMAT));
+        assert_eq!(pcm.data, &[5, 6]);
+    }
+
+    #[test]
+    fn extensible_format_with_float_samples_is_rejected() {
+        let mut fmt = fmt_body(WAVE_FORMAT_EXTENSIBLE, 16);
+        fmt.extend_from_slice(&[22, 0, 16, 0, 4, 0, 0, 0]);
+        // IEEE float sub format
+        fmt.extend_from_slice(&3u16.to_le_bytes());
+        fmt.extend_from_slice(&[0; 14]);
+        let wav = riff(&[chunk(b"fmt ", &fmt), chunk(b"data", &[5, 6])]);
+
+        assert!(unsupported(&wav).contains("0x0003"));
+    }
+
+    #[test]
+    fn list_chunk_before_fmt() {
+        let wav = riff(&[
+            chunk(b"LIST", b"INFOISFT\x04\x00\x00\x00test"),
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 16)),
+            chunk(b"data", &[7, 8]),
+        ]);
+
+        let pcm = detect_pcm(&wav).unwrap();
+        assert_eq!(pcm.format, Some(FORMAT));
+        assert_eq!(pcm.data, &[7, 8]);
+    }
+
+    #[test]
+    fn odd_sized_chunk_is_padded() {
+        let wav = riff(&[
+            chunk(b"junk", &[1, 2, 3]),
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 16)),
+            chunk(b"data", &[9, 10]),
+        ]);
+        // three bytes of body and one of padding
+        assert_eq!(&wav[12..24], b"junk\x03\x00\x00\x00\x01\x02\x03\x00");
+
+        let pcm = detect_pcm(&wav).unwrap();
+        assert_eq!(pcm.data, &[9, 10]);
+    }
+
+    #[test]
+    fn streaming_data_size() {
+        for placeholder in [0u32, u32::MAX].iter() {
+            let mut data = b"data".to_vec();
+            data.extend_from_slice(&placeholder.to_le_bytes());
+            data.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+            let wav = riff(&[chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 16)), data]);
+
+            let pcm = detect_pcm(&wav).unwrap();
+            assert_eq!(pcm.data, &[1, 2, 3, 4, 5, 6], "size {:#x}", placeholder);
+        }
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert!(unsupported(b"RIFF\x00\x00").contains("WAVE"));
+
+        let fmt = chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 16));
+        let wav = riff(&[fmt[..16].to_vec()]);
+        assert!(unsupported(&wav).contains("too short"));
+
+        let wav = riff(std::slice::from_ref(&fmt));
+        assert!(unsupported(&wav).contains("no data chunk"));
+
+        // data shorter than its declared size is taken as is
+        let mut wav = riff(&[fmt, chunk(b"data", &[1, 2, 3, 4])]);
+        wav.truncate(wav.len() - 2);
+        assert_eq!(detect_pcm(&wav).unwrap().data, &[1, 2]);
+    }
+
+    #[test]
+    fn data_before_fmt() {
+        let wav = riff(&[
+            chunk(b"data", &[1, 2]),
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 16)),
+        ]);
+        assert!(unsupported(&wav).contains("precedes"));
+    }
+
+    #[test]
+    fn unsupported_sample_format() {
+        let wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 8)),
+            chunk(b"data", &[1, 2]),
+        ]);
+        assert!(unsupported(&wav).contains("8 bit"));
+    }
+
+    #[test]
+    fn rejected_containers() {
+        assert!(unsupported(b"ID3\x04\x00\x00\x00\x00\x00\x00").starts_with("MP3"));
+        assert!(unsupported(b"OggS\x00\x02").starts_with("OGG"));
+        assert!(unsupported(b"fLaC\x00\x00\x00\x22").starts_with("FLAC"));
+        assert!(unsupported(b"RIFX\x00\x00\x00\x00WAVE").starts_with("big-endian"));
+    }
+
+    #[test]
+    fn raw_pcm() {
+        let pcm = detect_pcm(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(pcm.format, None);
+        assert_eq!(pcm.data, &[1, 2, 3, 4]);
+    }
+}
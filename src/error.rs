@@ -0,0 +1,144 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error returned by every public function of the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// Service account key could not be parsed or access token
+    /// could not be retrieved.
+    Auth(String),
+    /// Connection could not be established or request could not be sent.
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// gRPC call finished with non-ok status.
+    #[cfg(feature = "_rpc")]
+    Status(Box<tonic::Status>),
+    /// Response was received but could not be decoded.
+    Decode(String),
+    /// Service was used before its `initialize` was called.
+    NotInitialized(&'static str),
+    /// Service `initialize` was called more than once.
+    AlreadyInitialized(&'static str),
+    /// REST endpoint answered with unsuccessful status code.
+    Http { status: u16, body: String },
+    /// Operation did not complete in time.
+    Timeout(std::time::Duration),
+    /// Audio is in a format recognizer does not accept.
+    UnsupportedAudio(String),
+    /// Filesystem operation failed.
+    Io(std::io::Error),
+    /// Input can not be processed as given.
+    InvalidInput(String),
+    /// Same error returned for several operations, e.g. failure to retrieve
+    /// the token shared by all tasks of `create_tasks`.
+    Shared(Arc<Error>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Auth(e) => write!(f, "authentication failed: {}", e),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            #[cfg(feature = "_rpc")]
+            Error::Status(status) => write!(
+                f,
+                "rpc failed with status {:?}: {}",
+                status.code(),
+                status.message()
+            ),
+            Error::Decode(e) => write!(f, "failed to decode response: {}", e),
+            Error::NotInitialized(service) => write!(f, "{} service is not initialized", service),
+            Error::AlreadyInitialized(service) => {
+                write!(f, "{} service is already initialized", service)
+            }
+            Error::Http { status, body } => {
+                write!(f, "unexpected result with status code {}: {}", status, body)
+            }
+            Error::Timeout(timeout) => write!(f, "operation did not complete in {:?}", timeout),
+            Error::UnsupportedAudio(e) => write!(f, "unsupported audio: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::InvalidInput(e) => write!(f, "invalid input: {}", e),
+            Error::Shared(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e.as_ref()),
+            #[cfg(feature = "_rpc")]
+            Error::Status(status) => Some(status.as_ref()),
+            Error::Io(e) => Some(e),
+            Error::Shared(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "_rpc")]
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Status(Box::new(status))
+    }
+}
+
+#[cfg(feature = "_rpc")]
+impl From<tonic::transport::Error> for Error {
+    fn from(e: tonic::transport::Error) -> Self {
+        Error::Transport(Box::new(e))
+    }
+}
+
+#[cfg(feature = "_rpc")]
+impl From<tonic::metadata::errors::InvalidMetadataValue> for Error {
+    fn from(e: tonic::metadata::errors::InvalidMetadataValue) -> Self {
+        Error::Auth(e.to_string())
+    }
+}
+
+#[cfg(feature = "prost")]
+impl From<prost::DecodeError> for Error {
+    fn from(e: prost::DecodeError) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+#[cfg(feature = "_google")]
+impl From<yup_oauth2::Error> for Error {
+    fn from(e: yup_oauth2::Error) -> Self {
+        Error::Auth(e.to_string())
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Error::Decode(e.to_string())
+        } else {
+            Error::Transport(Box::new(e))
+        }
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e.to_string())
+    }
+}
+
+#[cfg(feature = "jsonwebtoken")]
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        Error::Auth(e.to_string())
+    }
+}
@@ -1,4 +1,8 @@
-crate::rpc_service!("logging", "https://www.googleapis.com/auth/cloud-platform");
+crate::rpc_service!(
+    LoggingClient,
+    "logging",
+    "https://www.googleapis.com/auth/cloud-platform"
+);
 use crate::google::generated::google::logging::v2;
 use std::collections::HashMap;
 
@@ -93,6 +97,11 @@ impl Timestamp {
             nanos: duration.nanos,
         }
     }
+
+    pub(crate) fn to_chrono(&self) -> chrono::DateTime<chrono::Utc> {
+        let since_epoch = std::time::Duration::new(self.seconds as u64, self.nanos as u32);
+        (std::time::UNIX_EPOCH + since_epoch).into()
+    }
 }
 
 #[repr(i32)]
@@ -118,6 +127,23 @@ pub enum LogSeverity {
     Emergency = 800,
 }
 
+impl LogSeverity {
+    /// Name of the severity as used in json representation of entries.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogSeverity::Default => "DEFAULT",
+            LogSeverity::Debug => "DEBUG",
+            LogSeverity::Info => "INFO",
+            LogSeverity::Notice => "NOTICE",
+            LogSeverity::Warning => "WARNING",
+            LogSeverity::Error => "ERROR",
+            LogSeverity::Critical => "CRITICAL",
+            LogSeverity::Alert => "ALERT",
+            LogSeverity::Emergency => "EMERGENCY",
+        }
+    }
+}
+
 pub struct LogEntry {
     pub timestamp: Timestamp,
     pub severity: LogSeverity,
@@ -126,6 +152,8 @@ pub struct LogEntry {
     pub payload: Payload,
     pub operation: Option<LogEntryOperation>,
     pub http_request: Option<HttpRequest>,
+    /// Resource name of the trace, `projects/[PROJECT_ID]/traces/[TRACE_ID]`.
+    pub trace: Option<String>,
 }
 
 impl From<LogEntry> for v2::LogEntry {
@@ -138,36 +166,46 @@ impl From<LogEntry> for v2::LogEntry {
             operation: val.operation,
             source_location: Some(val.source_code_entry),
             payload: Some(val.payload.into()),
+            trace: val.trace.unwrap_or_default(),
             ..Default::default()
         }
     }
 }
 
-pub async fn write_log(log: Log) -> Result<(), tonic::Status> {
-    let logger = SERVICE.get().unwrap();
-    let request = v2::WriteLogEntriesRequest {
-        log_name: format!("projects/{}/logs/{}", log.project_id, log.log_name),
-        resource: log.resource,
-        labels: log.labels,
-        entries: log.entries.into_iter().map(|x| x.into()).collect(),
-        partial_success: true,
-        dry_run: false,
-    };
-
-    let channel = logger.channel.clone();
-    let token = logger.auth.token(SCOPES).await.unwrap();
-    let bearer_token = format!("Bearer {}", token.as_str());
-    let token = MetadataValue::from_str(&bearer_token).unwrap();
-
-    let mut service = v2::logging_service_v2_client::LoggingServiceV2Client::with_interceptor(
-        channel,
-        move |mut req: Request<()>| {
-            let token = token.clone();
-            req.metadata_mut().insert("authorization", token);
-            Ok(req)
-        },
-    );
-
-    let response = service.write_log_entries(request).await;
-    response.map(|_| ())
+pub async fn write_log(log: Log) -> crate::Result<()> {
+    service()?.write_log(log).await
+}
+
+pub(crate) async fn write_log_entries(request: v2::WriteLogEntriesRequest) -> crate::Result<()> {
+    service()?.write_log_entries(request).await
+}
+
+impl LoggingClient {
+    pub async fn write_log(&self, log: Log) -> crate::Result<()> {
+        let request = v2::WriteLogEntriesRequest {
+            log_name: format!("projects/{}/logs/{}", log.project_id, log.log_name),
+            resource: log.resource,
+            labels: log.labels,
+            entries: log.entries.into_iter().map(|x| x.into()).collect(),
+            partial_success: true,
+            dry_run: false,
+        };
+        self.write_log_entries(request).await
+    }
+
+    pub(crate) async fn write_log_entries(
+        &self,
+        request: v2::WriteLogEntriesRequest,
+    ) -> crate::Result<()> {
+        let token = self.authorization().await?;
+
+        let mut service = v2::logging_service_v2_client::LoggingServiceV2Client::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        service.write_log_entries(request).await?;
+
+        Ok(())
+    }
 }
@@ -1,17 +1,68 @@
 mod google;
+mod queue;
+mod sink;
+#[cfg(feature = "google-logging-tracing")]
+mod subscriber;
 pub use google::*;
+pub use queue::{LoggerOptions, LoggerStats, OverflowPolicy};
+pub use sink::LogSink;
+#[cfg(feature = "google-logging-tracing")]
+pub use subscriber::{Logger, LoggingLayer};
 
 use once_cell::sync::{Lazy, OnceCell};
+use queue::LogQueue;
 use std::{collections::HashMap, option::Option, string::String, time::Duration};
-use tokio::sync::Mutex;
 
 static CURRENT_RESOURCE: OnceCell<google::MonitoredResource> = OnceCell::new();
 static PROJECT_ID: OnceCell<&'static str> = OnceCell::new();
 static LOG_NAME: OnceCell<&'static str> = OnceCell::new();
+static OPTIONS: OnceCell<LoggerOptions> = OnceCell::new();
+static DEFAULT_OPTIONS: Lazy<LoggerOptions> = Lazy::new(LoggerOptions::default);
 
-pub fn initialize_logger(project_id: &'static str, log_name: &'static str) {
-    PROJECT_ID.set(project_id).unwrap();
-    LOG_NAME.set(log_name).unwrap();
+fn options() -> &'static LoggerOptions {
+    OPTIONS.get().unwrap_or_else(|| &DEFAULT_OPTIONS)
+}
+
+pub fn initialize_logger(project_id: &'static str, log_name: &'static str) -> crate::Result<()> {
+    initialize_logger_with_options(project_id, log_name, LoggerOptions::default())
+}
+
+/// Queue options take effect only when set before the first entry is logged.
+pub fn initialize_logger_with_options(
+    project_id: &'static str,
+    log_name: &'static str,
+    options: LoggerOptions,
+) -> crate::Result<()> {
+    PROJECT_ID
+        .set(project_id)
+        .and_then(|_| LOG_NAME.set(log_name))
+        .map_err(|_| crate::Error::AlreadyInitialized("logger"))?;
+    let cloud_logging = options.sinks.contains(&LogSink::CloudLogging);
+    OPTIONS
+        .set(options)
+        .map_err(|_| crate::Error::AlreadyInitialized("logger"))?;
+    if cloud_logging {
+        LOGGER_QUEUE.start_writer();
+    }
+    Ok(())
+}
+
+/// Writes all queued entries, waiting for writes already in flight.
+/// Failed batches are retried as configured before this returns.
+pub async fn flush() -> crate::Result<()> {
+    LOGGER_QUEUE.flush().await
+}
+
+/// Flushes the queue and stops the background writer, entries logged
+/// afterwards are discarded. Meant to be called once before process exit,
+/// e.g. from a SIGTERM handler.
+pub async fn shutdown(timeout: Duration) -> crate::Result<()> {
+    LOGGER_QUEUE.shutdown(timeout).await
+}
+
+/// Counters of entries queued, written and dropped by the logger.
+pub fn logger_stats() -> LoggerStats {
+    LOGGER_QUEUE.stats()
 }
 
 /// https://cloud.google.com/monitoring/api/resources
@@ -20,36 +71,19 @@ pub fn describe_current_resource(
     instance_id: String,
     project_id: String,
     zone: String,
-) {
+) -> crate::Result<()> {
     let mut labels = HashMap::new();
     labels.insert("instance_id".to_owned(), instance_id);
     labels.insert("project_id".to_owned(), project_id);
     labels.insert("zone".to_owned(), zone);
     CURRENT_RESOURCE
         .set(google::MonitoredResource { r#type, labels })
-        .unwrap();
+        .map_err(|_| crate::Error::AlreadyInitialized("monitored resource"))
 }
 
-static LOGGER_QUEUE: Lazy<Mutex<Vec<LogEntry>>> = Lazy::new(|| {
-    tokio::spawn(async {
-        loop {
-            tokio::time::sleep(Duration::from_secs(5)).await;
-            let queue = LOGGER_QUEUE.lock().await.drain(..).collect();
-            let res = google::write_log(google::Log {
-                project_id: PROJECT_ID.get().unwrap(),
-                log_name: LOG_NAME.get().unwrap(),
-                resource: CURRENT_RESOURCE.get().cloned(),
-                labels: Default::default(),
-                entries: queue,
-            })
-            .await;
-            if let Err(e) = res {
-                eprintln!("[GOOGLE LOGGER] Failed to write log: {}", e);
-            }
-        }
-    });
-    Mutex::new(Vec::with_capacity(32))
-});
+/// Writer of the queue is started by [`initialize_logger`] or the first
+/// entry logged within a tokio runtime.
+static LOGGER_QUEUE: Lazy<LogQueue> = Lazy::new(|| LogQueue::new(options().clone()));
 
 pub struct HttpRequest {
     /// The request method. Examples: `"GET"`, `"HEAD"`, `"PUT"`, `"POST"`.
@@ -161,6 +195,7 @@ impl HttpRequest {
 pub struct LogContext {
     request: Option<google::HttpRequest>,
     operation: Option<String>,
+    trace: Option<String>,
     labels: std::collections::HashMap<String, String>,
 }
 
@@ -184,6 +219,12 @@ impl LogContext {
         self
     }
 
+    /// Accepts trace id, or value of `X-Cloud-Trace-Context` header.
+    pub fn trace(mut self, trace: impl AsRef<str>) -> LogContext {
+        self.trace = Some(trace_id(trace.as_ref()));
+        self
+    }
+
     pub fn with(mut self, context: LogContext) -> LogContext {
         if let Some(request) = context.request {
             self.request = Some(request)
@@ -191,6 +232,9 @@ impl LogContext {
         if let Some(operation) = context.operation {
             self.operation = Some(operation)
         }
+        if let Some(trace) = context.trace {
+            self.trace = Some(trace)
+        }
         for (label, value) in context.labels {
             let _ = self.labels.insert(label, value);
         }
@@ -261,6 +305,12 @@ impl LogBuilder {
         self
     }
 
+    /// Accepts trace id, or value of `X-Cloud-Trace-Context` header.
+    pub fn trace(mut self, trace: impl AsRef<str>) -> LogBuilder {
+        self.context.trace = Some(trace_id(trace.as_ref()));
+        self
+    }
+
     pub fn last(mut self) -> LogBuilder {
         self.operation_last = true;
         self
@@ -272,77 +322,104 @@ impl LogBuilder {
     }
 
     pub fn send_json(mut self, json: impl serde::Serialize) {
-        let json = serde_json::to_value(json).unwrap();
-        let payload = match json {
-            serde_json::Value::Object(map) => Payload::Json(map),
-            val => Payload::Text(val.to_string()),
-        };
-
-        self.payload = Some(payload);
+        self.payload = Some(json_payload(json));
         self.build_and_push();
     }
 
+    /// Same as [`send_text`](Self::send_text), but with
+    /// [`OverflowPolicy::Block`] waits for room in the full queue.
+    pub async fn send_text_async(mut self, text: impl Into<String>) {
+        self.payload = Some(Payload::Text(text.into()));
+        self.build_and_push_async().await;
+    }
+
+    /// Same as [`send_json`](Self::send_json), but with
+    /// [`OverflowPolicy::Block`] waits for room in the full queue.
+    pub async fn send_json_async(mut self, json: impl serde::Serialize) {
+        self.payload = Some(json_payload(json));
+        self.build_and_push_async().await;
+    }
+
     fn build_and_push(self) {
-        let enabled_google_logging = option_env!("GOOGLE_LOGGING_ENABLED");
-        if let Some("true") = enabled_google_logging {
-            let entry = LogEntry {
-                timestamp: self.time,
-                severity: self.severity,
-                labels: self.context.labels,
-                source_code_entry: google::LogEntrySourceLocation {
-                    file: self.file.to_owned(),
-                    line: self.line,
-                    function: self.fn_name.to_owned(),
-                },
-                payload: self.payload.unwrap(),
-                http_request: self.context.request,
-                operation: if let Some(operation) = self.context.operation {
-                    Some(google::LogEntryOperation {
-                        id: operation,
-                        producer: String::new(),
-                        first: self.operation_first,
-                        last: self.operation_last,
-                    })
-                } else {
-                    None
-                },
-            };
-
-            match LOGGER_QUEUE.try_lock() {
-                Ok(mut guard) => {
-                    guard.push(entry);
-                }
-                Err(_) => {
-                    tokio::spawn(async {
-                        LOGGER_QUEUE.lock().await.push(entry);
-                    });
-                }
+        if let Some(entry) = self.build_and_print() {
+            LOGGER_QUEUE.start_writer();
+            LOGGER_QUEUE.push(entry.into());
+        }
+    }
+
+    async fn build_and_push_async(self) {
+        if let Some(entry) = self.build_and_print() {
+            LOGGER_QUEUE.start_writer();
+            LOGGER_QUEUE.push_async(entry.into()).await;
+        }
+    }
+
+    /// Prints the entry to stdout sinks, returns it
+    /// if it should be written to Cloud Logging.
+    fn build_and_print(self) -> Option<LogEntry> {
+        let entry = self.build();
+        let sinks = &options().sinks;
+        for sink in sinks {
+            match sink {
+                LogSink::Stdout => sink::print_pretty(&entry),
+                LogSink::StdoutJson => sink::print_json(&entry),
+                LogSink::CloudLogging => {}
             }
+        }
+        if sinks.contains(&LogSink::CloudLogging) {
+            Some(entry)
         } else {
-            let text = match self.payload.unwrap() {
-                Payload::Text(s) => s,
-                Payload::Json(map) => format!("{:?}", map),
-            };
-            let labels = self
-                .context
-                .labels
-                .into_iter()
-                .map(|x| format!("[{} = {}]", x.0, x.1))
-                .collect::<Vec<String>>()
-                .join(" ");
-            let time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
-            println!(
-                "{:<29} | {}\n[{}]{} -> {}\n",
-                format!("{}:{}", self.file, self.line),
-                self.fn_name,
-                time,
-                labels,
-                text.replace("\n", "\n>   ")
-            );
+            None
+        }
+    }
+
+    fn build(self) -> LogEntry {
+        LogEntry {
+            timestamp: self.time,
+            severity: self.severity,
+            labels: self.context.labels,
+            source_code_entry: google::LogEntrySourceLocation {
+                file: self.file.to_owned(),
+                line: self.line,
+                function: self.fn_name.to_owned(),
+            },
+            payload: self.payload.unwrap(),
+            http_request: self.context.request,
+            operation: if let Some(operation) = self.context.operation {
+                Some(google::LogEntryOperation {
+                    id: operation,
+                    producer: String::new(),
+                    first: self.operation_first,
+                    last: self.operation_last,
+                })
+            } else {
+                None
+            },
+            trace: self.context.trace.map(|trace| match PROJECT_ID.get() {
+                Some(project_id) => format!("projects/{}/traces/{}", project_id, trace),
+                None => trace,
+            }),
         }
     }
 }
 
+fn json_payload(json: impl serde::Serialize) -> Payload {
+    match serde_json::to_value(json) {
+        Ok(serde_json::Value::Object(map)) => Payload::Json(map),
+        Ok(val) => Payload::Text(val.to_string()),
+        Err(e) => Payload::Text(format!("Failed to serialize json payload: {}", e)),
+    }
+}
+
+/// Header value is formatted as `TRACE_ID/SPAN_ID;o=TRACE_TRUE`.
+fn trace_id(trace: &str) -> String {
+    trace
+        .split(&['/', ';'][..])
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! function {
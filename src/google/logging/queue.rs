@@ -0,0 +1,602 @@
+use super::{google, LogSeverity, LogSink, CURRENT_RESOURCE, LOG_NAME, PROJECT_ID};
+use crate::google::generated::google::logging::v2;
+use prost::Message;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Shortest time between writes, shorter intervals would keep
+/// the writer busy with near empty requests.
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What happens to a new entry when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Oldest queued entry is dropped to make room.
+    DropOldest,
+    /// New entry is dropped.
+    DropNewest,
+    /// Entries sent with `send_text_async` or `send_json_async` wait up to
+    /// the given time for the writer to take queued entries, then the new
+    /// entry is dropped. Other entries are dropped right away, as with
+    /// `DropNewest`, so that logging never blocks a runtime thread.
+    Block(Duration),
+}
+
+/// Settings of the logger, its sinks and the queue entries
+/// wait in before being written to Cloud Logging.
+#[derive(Debug, Clone)]
+pub struct LoggerOptions {
+    pub(super) sinks: Vec<LogSink>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    max_retries: u32,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    flush_interval: Duration,
+    batch_entries: usize,
+    batch_bytes: usize,
+}
+
+impl Default for LoggerOptions {
+    fn default() -> Self {
+        LoggerOptions {
+            sinks: LogSink::defaults(),
+            capacity: 10_000,
+            overflow: OverflowPolicy::DropOldest,
+            max_retries: 5,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            flush_interval: Duration::from_secs(5),
+            batch_entries: 1000,
+            // service rejects requests over 10MB
+            batch_bytes: 5_000_000,
+        }
+    }
+}
+
+impl LoggerOptions {
+    pub fn new() -> LoggerOptions {
+        Default::default()
+    }
+
+    /// Replaces default sinks, which are Cloud Logging when
+    /// `GOOGLE_LOGGING_ENABLED` is `true` at runtime or build time,
+    /// and stdout otherwise.
+    pub fn sinks(mut self, sinks: &[LogSink]) -> LoggerOptions {
+        self.sinks = sinks.to_vec();
+        self
+    }
+
+    /// Maximum number of entries waiting to be written.
+    pub fn capacity(mut self, capacity: usize) -> LoggerOptions {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn overflow(mut self, policy: OverflowPolicy) -> LoggerOptions {
+        self.overflow = policy;
+        self
+    }
+
+    /// Failed batch is retried up to `max_retries` times, waiting `min_backoff`
+    /// before the first retry and twice as long before each next one,
+    /// up to `max_backoff`.
+    pub fn retries(
+        mut self,
+        max_retries: u32,
+        min_backoff: Duration,
+        max_backoff: Duration,
+    ) -> LoggerOptions {
+        self.max_retries = max_retries;
+        self.min_backoff = min_backoff;
+        self.max_backoff = max_backoff.max(min_backoff);
+        self
+    }
+
+    /// Time between writes of queued entries, at least 100ms. Entries
+    /// of `Error` and higher severity, or a full batch, are written right away.
+    pub fn flush_interval(mut self, interval: Duration) -> LoggerOptions {
+        self.flush_interval = interval.max(MIN_FLUSH_INTERVAL);
+        self
+    }
+
+    /// Limits of a single write request, queued entries are split
+    /// into as many requests as needed. Entries larger than `max_bytes`
+    /// on their own are dropped.
+    pub fn batch_limits(mut self, max_entries: usize, max_bytes: usize) -> LoggerOptions {
+        self.batch_entries = max_entries.max(1);
+        self.batch_bytes = max_bytes;
+        self
+    }
+}
+
+/// Counters of the logger queue since start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoggerStats {
+    /// Entries waiting to be written.
+    pub queued: usize,
+    pub written: u64,
+    /// Entries dropped because the queue was full.
+    pub dropped_on_overflow: u64,
+    /// Entries dropped after all attempts to write their batch failed,
+    /// or for being too large to write.
+    pub dropped_on_failure: u64,
+}
+
+pub(crate) struct LogQueue {
+    options: LoggerOptions,
+    entries: Mutex<VecDeque<v2::LogEntry>>,
+    /// Wakes the writer before the flush interval ends.
+    wake: tokio::sync::Notify,
+    /// Wakes producers waiting for room when entries are taken out.
+    space: tokio::sync::Notify,
+    /// Held for the whole write of a batch, so flush waits for writes in flight.
+    writing: tokio::sync::Mutex<()>,
+    closed: AtomicBool,
+    writer_started: AtomicBool,
+    written: AtomicU64,
+    dropped_on_overflow: AtomicU64,
+    dropped_on_failure: AtomicU64,
+}
+
+impl LogQueue {
+    pub(crate) fn new(options: LoggerOptions) -> LogQueue {
+        LogQueue {
+            entries: Mutex::new(VecDeque::with_capacity(options.capacity.min(1024))),
+            options,
+            wake: tokio::sync::Notify::new(),
+            space: tokio::sync::Notify::new(),
+            writing: tokio::sync::Mutex::new(()),
+            closed: AtomicBool::new(false),
+            writer_started: AtomicBool::new(false),
+            written: AtomicU64::new(0),
+            dropped_on_overflow: AtomicU64::new(0),
+            dropped_on_failure: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn push(&self, entry: v2::LogEntry) {
+        if self.try_push(entry).is_some() {
+            self.dropped_on_overflow.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Same as [`push`](Self::push), but with `Block` policy waits
+    /// for room in the full queue.
+    pub(crate) async fn push_async(&self, entry: v2::LogEntry) {
+        let timeout = match self.options.overflow {
+            OverflowPolicy::Block(timeout) => timeout,
+            _ => return self.push(entry),
+        };
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut entry = entry;
+        loop {
+            // created before the check, so that entries taken right after it wake us
+            let space = self.space.notified();
+            entry = match self.try_push(entry) {
+                Some(entry) => entry,
+                None => return,
+            };
+            if tokio::time::timeout_at(deadline, space).await.is_err() {
+                self.dropped_on_overflow.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Queues the entry, making room as the overflow policy says. Returns
+    /// the entry back if the queue is full and the policy is `Block`.
+    fn try_push(&self, entry: v2::LogEntry) -> Option<v2::LogEntry> {
+        if self.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.options.capacity {
+            match self.options.overflow {
+                OverflowPolicy::DropOldest => {
+                    entries.pop_front();
+                    self.dropped_on_overflow.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped_on_overflow.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                OverflowPolicy::Block(_) => {
+                    // make the writer free room without waiting for the interval
+                    self.wake.notify_one();
+                    return Some(entry);
+                }
+            }
+        }
+        let urgent = entry.severity >= LogSeverity::Error as i32;
+        entries.push_back(entry);
+        if urgent || entries.len() >= self.options.batch_entries {
+            self.wake.notify_one();
+        }
+        None
+    }
+
+    fn take_all(&self) -> Vec<v2::LogEntry> {
+        let entries = self.entries.lock().unwrap().drain(..).collect();
+        self.space.notify_waiters();
+        entries
+    }
+
+    pub(crate) fn stats(&self) -> LoggerStats {
+        LoggerStats {
+            queued: self.entries.lock().unwrap().len(),
+            written: self.written.load(Ordering::Relaxed),
+            dropped_on_overflow: self.dropped_on_overflow.load(Ordering::Relaxed),
+            dropped_on_failure: self.dropped_on_failure.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Spawns the writer on the current runtime, unless it runs already.
+    /// Outside of a runtime entries stay queued until a writer is started
+    /// or [`flush`](Self::flush) is awaited.
+    pub(crate) fn start_writer(&'static self) {
+        if self.writer_started.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            if !self.writer_started.swap(true, Ordering::Relaxed) {
+                runtime.spawn(self.run());
+            }
+        }
+    }
+
+    /// Writes queued entries every few seconds until shut down.
+    async fn run(&self) {
+        loop {
+            let _ = tokio::time::timeout(self.options.flush_interval, self.wake.notified()).await;
+            if self.closed.load(Ordering::Relaxed) {
+                return;
+            }
+            if let Err(e) = self.flush().await {
+                eprintln!("[GOOGLE LOGGER] {}", e);
+            }
+        }
+    }
+
+    /// Writes all queued entries, waiting for a write in flight first.
+    pub(crate) async fn flush(&self) -> crate::Result<()> {
+        let (project_id, log_name) = match (PROJECT_ID.get(), LOG_NAME.get()) {
+            (Some(project_id), Some(log_name)) => (project_id, log_name),
+            _ => return Err(crate::Error::NotInitialized("logger")),
+        };
+        let _writing = self.writing.lock().await;
+        let batches = self.batches(self.take_all());
+        let mut unwritten = Unwritten {
+            queue: self,
+            entries: batches.iter().map(|batch| batch.len() as u64).sum(),
+        };
+        for entries in batches {
+            let count = entries.len() as u64;
+            self.write(v2::WriteLogEntriesRequest {
+                log_name: format!("projects/{}/logs/{}", project_id, log_name),
+                resource: CURRENT_RESOURCE.get().cloned(),
+                entries,
+                partial_success: true,
+                ..Default::default()
+            })
+            .await;
+            unwritten.entries -= count;
+        }
+        Ok(())
+    }
+
+    /// Splits entries into batches within configured limits.
+    fn batches(&self, entries: Vec<v2::LogEntry>) -> Vec<Vec<v2::LogEntry>> {
+        let mut batches = vec![];
+        let mut batch = vec![];
+        let mut batch_bytes = 0;
+        for entry in entries {
+            // size of the entry as a repeated field of the request
+            let length = entry.encoded_len();
+            let bytes = 1 + prost::length_delimiter_len(length) + length;
+            if bytes > self.options.batch_bytes {
+                eprintln!(
+                    "[GOOGLE LOGGER] Dropping entry of {} bytes, over the batch limit",
+                    bytes
+                );
+                self.dropped_on_failure.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if batch.len() == self.options.batch_entries
+                || batch_bytes + bytes > self.options.batch_bytes
+            {
+                batches.push(std::mem::take(&mut batch));
+                batch_bytes = 0;
+            }
+            batch.push(entry);
+            batch_bytes += bytes;
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+        batches
+    }
+
+    /// Stops accepting entries and flushes the queue. Entries taken
+    /// for writing when the time runs out are counted as dropped.
+    pub(crate) async fn shutdown(&self, timeout: Duration) -> crate::Result<()> {
+        self.closed.store(true, Ordering::Relaxed);
+        tokio::time::timeout(timeout, self.flush())
+            .await
+            .map_err(|_| crate::Error::Timeout(timeout))?
+    }
+
+    /// Writes batch retrying with exponential backoff,
+    /// entries of the batch are dropped once retries are exhausted.
+    async fn write(&self, request: v2::WriteLogEntriesRequest) {
+        let count = request.entries.len() as u64;
+        let mut backoff = self.options.min_backoff;
+        let mut retries = 0;
+        loop {
+            match google::write_log_entries(request.clone()).await {
+                Ok(()) => {
+                    self.written.fetch_add(count, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) if retries < self.options.max_retries => {
+                    eprintln!(
+                        "[GOOGLE LOGGER] Failed to write log, retrying in {:?}: {}",
+                        backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.options.max_backoff);
+                    retries += 1;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "[GOOGLE LOGGER] Failed to write log, dropping {} entries: {}",
+                        count, e
+                    );
+                    self.dropped_on_failure.fetch_add(count, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Entries taken out of the queue by a flush and not written yet,
+/// counted as dropped if the flush is cancelled.
+struct Unwritten<'a> {
+    queue: &'a LogQueue,
+    entries: u64,
+}
+
+impl Drop for Unwritten<'_> {
+    fn drop(&mut self) {
+        if self.entries > 0 {
+            eprintln!(
+                "[GOOGLE LOGGER] Flush was cancelled, dropping {} entries",
+                self.entries
+            );
+            self.queue
+                .dropped_on_failure
+                .fetch_add(self.entries, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use v2::log_entry::Payload;
+
+    /// Entry taking 100 bytes in a write request.
+    fn entry(text: char) -> v2::LogEntry {
+        v2::LogEntry {
+            payload: Some(Payload::TextPayload(text.to_string().repeat(96))),
+            ..Default::default()
+        }
+    }
+
+    fn text(entry: &v2::LogEntry) -> char {
+        match &entry.payload {
+            Some(Payload::TextPayload(text)) => text.chars().next().unwrap(),
+            _ => panic!("entry without text"),
+        }
+    }
+
+    fn queue(options: LoggerOptions) -> LogQueue {
+        LogQueue::new(options.sinks(&[LogSink::CloudLogging]))
+    }
+
+    #[test]
+    fn drop_oldest_on_overflow() {
+        let queue = queue(LoggerOptions::new().capacity(2));
+        for c in "abcd".chars() {
+            queue.push(entry(c));
+        }
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.dropped_on_overflow), (2, 2));
+        let texts: Vec<char> = queue.take_all().iter().map(text).collect();
+        assert_eq!(texts, vec!['c', 'd']);
+    }
+
+    #[test]
+    fn drop_newest_on_overflow() {
+        let queue = queue(
+            LoggerOptions::new()
+                .capacity(2)
+                .overflow(OverflowPolicy::DropNewest),
+        );
+        for c in "abcd".chars() {
+            queue.push(entry(c));
+        }
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.dropped_on_overflow), (2, 2));
+        let texts: Vec<char> = queue.take_all().iter().map(text).collect();
+        assert_eq!(texts, vec!['a', 'b']);
+    }
+
+    #[test]
+    fn block_drops_entries_not_waiting() {
+        let queue = queue(
+            LoggerOptions::new()
+                .capacity(1)
+                .overflow(OverflowPolicy::Block(Duration::from_millis(20))),
+        );
+        queue.push(entry('a'));
+        // synchronous push never waits
+        queue.push(entry('b'));
+        assert_eq!(queue.stats().dropped_on_overflow, 1);
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(queue.push_async(entry('c')));
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.dropped_on_overflow), (1, 2));
+        let texts: Vec<char> = queue.take_all().iter().map(text).collect();
+        assert_eq!(texts, vec!['a']);
+    }
+
+    #[test]
+    fn blocked_producer_resumes_after_flush() {
+        let _ = PROJECT_ID.set("project");
+        let _ = LOG_NAME.set("log");
+        // writes fail without logging client, entries are dropped without retries
+        let queue = Arc::new(queue(
+            LoggerOptions::new()
+                .capacity(1)
+                .overflow(OverflowPolicy::Block(Duration::from_secs(10)))
+                .retries(0, Duration::ZERO, Duration::ZERO),
+        ));
+        queue.push(entry('a'));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let producer = tokio::spawn({
+                let queue = queue.clone();
+                async move { queue.push_async(entry('b')).await }
+            });
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!producer.is_finished());
+            assert_eq!(queue.stats().queued, 1);
+
+            queue.flush().await.unwrap();
+            tokio::time::timeout(Duration::from_secs(1), producer)
+                .await
+                .expect("producer is still blocked")
+                .unwrap();
+        });
+
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.dropped_on_overflow), (1, 0));
+        assert_eq!(stats.dropped_on_failure, 1);
+        let texts: Vec<char> = queue.take_all().iter().map(text).collect();
+        assert_eq!(texts, vec!['b']);
+    }
+
+    #[test]
+    fn writer_starts_within_runtime() {
+        static QUEUE: once_cell::sync::Lazy<LogQueue> =
+            once_cell::sync::Lazy::new(|| queue(LoggerOptions::new()));
+
+        QUEUE.start_writer();
+        QUEUE.push(entry('a'));
+        assert!(!QUEUE.writer_started.load(Ordering::Relaxed));
+        assert_eq!(QUEUE.stats().queued, 1);
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(async { QUEUE.start_writer() });
+        assert!(QUEUE.writer_started.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn closed_queue_ignores_entries() {
+        let queue = queue(LoggerOptions::new());
+        queue.closed.store(true, Ordering::Relaxed);
+        queue.push(entry('a'));
+        assert_eq!(queue.stats(), LoggerStats::default());
+    }
+
+    #[test]
+    fn flush_interval_clamped() {
+        let options = LoggerOptions::new().flush_interval(Duration::ZERO);
+        assert_eq!(options.flush_interval, MIN_FLUSH_INTERVAL);
+        let options = LoggerOptions::new().flush_interval(Duration::from_secs(1));
+        assert_eq!(options.flush_interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn entry_size_in_request() {
+        let request = v2::WriteLogEntriesRequest {
+            entries: vec![entry('a'), entry('b')],
+            ..Default::default()
+        };
+        assert_eq!(request.encoded_len(), 200);
+    }
+
+    #[test]
+    fn batches_limited_by_entries() {
+        let queue = queue(LoggerOptions::new().batch_limits(2, 10_000));
+        let batches = queue.batches("abcde".chars().map(entry).collect());
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(text(&batches[2][0]), 'e');
+    }
+
+    #[test]
+    fn batches_limited_by_bytes() {
+        let queue = queue(LoggerOptions::new().batch_limits(100, 250));
+        let batches = queue.batches("abcde".chars().map(entry).collect());
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(queue.stats().dropped_on_failure, 0);
+    }
+
+    #[test]
+    fn oversized_entries_dropped() {
+        let queue = queue(LoggerOptions::new().batch_limits(100, 150));
+        let mut large = entry('b');
+        large.payload = Some(Payload::TextPayload("b".repeat(200)));
+        let batches = queue.batches(vec![entry('a'), large, entry('c')]);
+
+        let texts: Vec<Vec<char>> = batches
+            .iter()
+            .map(|batch| batch.iter().map(text).collect())
+            .collect();
+        assert_eq!(texts, vec![vec!['a'], vec!['c']]);
+        assert_eq!(queue.stats().dropped_on_failure, 1);
+    }
+
+    #[test]
+    fn shutdown_timeout_counts_unwritten_entries() {
+        let _ = PROJECT_ID.set("project");
+        let _ = LOG_NAME.set("log");
+        // writes fail without logging client, the first retry waits a second
+        let queue = queue(LoggerOptions::new().batch_limits(2, 10_000));
+        for c in "abcde".chars() {
+            queue.push(entry(c));
+        }
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(queue.shutdown(Duration::from_millis(50)));
+
+        assert!(matches!(result, Err(crate::Error::Timeout(_))));
+        let stats = queue.stats();
+        assert_eq!((stats.queued, stats.written), (0, 0));
+        assert_eq!(stats.dropped_on_failure, 5);
+    }
+}
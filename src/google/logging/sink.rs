@@ -0,0 +1,244 @@
+use super::{google::HttpRequest, LogEntry, Payload};
+use serde_json::{json, Map, Value};
+
+/// Destination of log entries, several can be used at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    /// Entries are queued and written through the Cloud Logging API.
+    CloudLogging,
+    /// Human readable lines on stdout.
+    Stdout,
+    /// One JSON object per line on stdout, in the format the logging
+    /// agent of GCP runtimes turns into structured entries. Payload fields
+    /// the agent would read as metadata, like `severity`, go under `payload`.
+    StdoutJson,
+}
+
+impl LogSink {
+    pub(crate) fn defaults() -> Vec<LogSink> {
+        let enabled = std::env::var("GOOGLE_LOGGING_ENABLED").ok();
+        let enabled = enabled.as_deref().or(option_env!("GOOGLE_LOGGING_ENABLED"));
+        if enabled == Some("true") {
+            vec![LogSink::CloudLogging]
+        } else {
+            vec![LogSink::Stdout]
+        }
+    }
+}
+
+pub(crate) fn print_pretty(entry: &LogEntry) {
+    let text = match &entry.payload {
+        Payload::Text(s) => s.clone(),
+        Payload::Json(map) => format!("{:?}", map),
+    };
+    let labels = entry
+        .labels
+        .iter()
+        .map(|x| format!("[{} = {}]", x.0, x.1))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let time = entry
+        .timestamp
+        .to_chrono()
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, false);
+    let source = &entry.source_code_entry;
+    println!(
+        "{:<29} | {}\n[{}]{} -> {}\n",
+        format!("{}:{}", source.file, source.line),
+        source.function,
+        time,
+        labels,
+        text.replace("\n", "\n>   ")
+    );
+}
+
+/// Fields the logging agent reads entry metadata from, along with
+/// every field starting with `logging.googleapis.com/`.
+const SPECIAL_FIELDS: &[&str] = &[
+    "severity",
+    "time",
+    "timestamp",
+    "timestampSeconds",
+    "timestampNanos",
+    "httpRequest",
+    MOVED_FIELDS,
+];
+/// Payload fields named as special ones are moved under this field.
+const MOVED_FIELDS: &str = "payload";
+
+pub(crate) fn print_json(entry: &LogEntry) {
+    println!("{}", json_line(entry));
+}
+
+/// Fields of json payload are kept at top level, where the agent
+/// expects them, text payload goes to `message`.
+fn json_line(entry: &LogEntry) -> Value {
+    let mut line = Map::new();
+    match &entry.payload {
+        Payload::Text(text) => {
+            line.insert("message".to_owned(), text.clone().into());
+        }
+        Payload::Json(map) => {
+            let mut moved = Map::new();
+            for (field, value) in map {
+                if SPECIAL_FIELDS.contains(&field.as_str())
+                    || field.starts_with("logging.googleapis.com/")
+                {
+                    moved.insert(field.clone(), value.clone());
+                } else {
+                    line.insert(field.clone(), value.clone());
+                }
+            }
+            if !moved.is_empty() {
+                line.insert(MOVED_FIELDS.to_owned(), moved.into());
+            }
+        }
+    }
+    line.insert("severity".to_owned(), entry.severity.as_str().into());
+    line.insert(
+        "time".to_owned(),
+        entry
+            .timestamp
+            .to_chrono()
+            .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+            .into(),
+    );
+    if !entry.labels.is_empty() {
+        line.insert(
+            "logging.googleapis.com/labels".to_owned(),
+            json!(entry.labels),
+        );
+    }
+    let source = &entry.source_code_entry;
+    line.insert(
+        "logging.googleapis.com/sourceLocation".to_owned(),
+        json!({
+            "file": source.file,
+            // int64 fields are strings in json representation
+            "line": source.line.to_string(),
+            "function": source.function,
+        }),
+    );
+    if let Some(operation) = &entry.operation {
+        line.insert(
+            "logging.googleapis.com/operation".to_owned(),
+            json!({
+                "id": operation.id,
+                "producer": operation.producer,
+                "first": operation.first,
+                "last": operation.last,
+            }),
+        );
+    }
+    if let Some(request) = &entry.http_request {
+        line.insert("httpRequest".to_owned(), http_request_json(request));
+    }
+    if let Some(trace) = &entry.trace {
+        line.insert(
+            "logging.googleapis.com/trace".to_owned(),
+            trace.clone().into(),
+        );
+    }
+    Value::Object(line)
+}
+
+/// Fields with default values are left out, as in json representation
+/// of the proto message.
+fn http_request_json(request: &HttpRequest) -> Value {
+    let mut json = Map::new();
+    let mut insert_str = |field: &str, value: &str| {
+        if !value.is_empty() {
+            json.insert(field.to_owned(), value.into());
+        }
+    };
+    insert_str("requestMethod", &request.request_method);
+    insert_str("requestUrl", &request.request_url);
+    insert_str("userAgent", &request.user_agent);
+    insert_str("remoteIp", &request.remote_ip);
+    insert_str("serverIp", &request.server_ip);
+    insert_str("referer", &request.referer);
+    insert_str("protocol", &request.protocol);
+    if request.request_size != 0 {
+        json.insert(
+            "requestSize".to_owned(),
+            request.request_size.to_string().into(),
+        );
+    }
+    if request.response_size != 0 {
+        json.insert(
+            "responseSize".to_owned(),
+            request.response_size.to_string().into(),
+        );
+    }
+    if request.status != 0 {
+        json.insert("status".to_owned(), request.status.into());
+    }
+    if let Some(latency) = &request.latency {
+        json.insert(
+            "latency".to_owned(),
+            format!("{}.{:09}s", latency.seconds, latency.nanos).into(),
+        );
+    }
+    Value::Object(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::google::logging::{LogSeverity, Timestamp};
+
+    fn entry(payload: Payload) -> LogEntry {
+        LogEntry {
+            timestamp: Timestamp::now(),
+            severity: LogSeverity::Warning,
+            labels: Default::default(),
+            source_code_entry: Default::default(),
+            payload,
+            operation: None,
+            http_request: Some(HttpRequest {
+                status: 200,
+                ..Default::default()
+            }),
+            trace: Some("projects/p/traces/t".to_owned()),
+        }
+    }
+
+    #[test]
+    fn text_goes_to_message() {
+        let line = json_line(&entry(Payload::Text("hello".to_owned())));
+        assert_eq!(line["message"], "hello");
+        assert_eq!(line["severity"], "WARNING");
+        assert_eq!(line.get(MOVED_FIELDS), None);
+    }
+
+    #[test]
+    fn colliding_fields_are_moved() {
+        let payload = json!({
+            "message": "hello",
+            "user": "id",
+            "severity": "user severity",
+            "time": 1,
+            "httpRequest": {"status": 500},
+            "payload": "user payload",
+            "logging.googleapis.com/trace": "user trace",
+        });
+        let line = json_line(&entry(Payload::Json(payload.as_object().unwrap().clone())));
+
+        assert_eq!(line["message"], "hello");
+        assert_eq!(line["user"], "id");
+        assert_eq!(line["severity"], "WARNING");
+        assert!(line["time"].is_string());
+        assert_eq!(line["httpRequest"], json!({"status": 200}));
+        assert_eq!(line["logging.googleapis.com/trace"], "projects/p/traces/t");
+        assert_eq!(
+            line[MOVED_FIELDS],
+            json!({
+                "severity": "user severity",
+                "time": 1,
+                "httpRequest": {"status": 500},
+                "payload": "user payload",
+                "logging.googleapis.com/trace": "user trace",
+            })
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use super::{LogBuilder, LogSeverity, Payload};
+use std::{collections::BTreeMap, fmt};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Events of crates the logger itself writes through are not forwarded,
+/// otherwise every write would produce new entries to write.
+const IGNORED_TARGETS: &[&str] = &[
+    "h2",
+    "hyper",
+    "tonic",
+    "tower",
+    "rustls",
+    "tokio_rustls",
+    "want",
+    "mio",
+    "yup_oauth2",
+];
+
+fn is_ignored(target: &str) -> bool {
+    IGNORED_TARGETS.iter().any(|ignored| {
+        target
+            .strip_prefix(ignored)
+            .map(|rest| rest.is_empty() || rest.starts_with("::"))
+            .unwrap_or(false)
+    })
+}
+
+// --------------------------------
+// TRACING
+// --------------------------------
+
+/// Forwards `tracing` events to the logger queue.
+///
+/// Event level is mapped to severity, the name of the innermost span to
+/// operation id, and span fields to labels. Event fields other than
+/// `message` go to `jsonPayload`, or to labels when
+/// [`fields_as_labels`](LoggingLayer::fields_as_labels) is set.
+#[derive(Debug, Clone, Default)]
+pub struct LoggingLayer {
+    fields_as_labels: bool,
+}
+
+impl LoggingLayer {
+    pub fn new() -> LoggingLayer {
+        Default::default()
+    }
+
+    pub fn fields_as_labels(mut self) -> LoggingLayer {
+        self.fields_as_labels = true;
+        self
+    }
+}
+
+/// Fields of a span, kept in its extensions.
+struct SpanLabels(BTreeMap<String, String>);
+
+impl<S> Layer<S> for LoggingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut()
+                .insert(SpanLabels(fields.into_labels()));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<SpanLabels>() {
+                Some(labels) => labels.0.extend(fields.into_labels()),
+                None => extensions.insert(SpanLabels(fields.into_labels())),
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if is_ignored(metadata.target()) {
+            return;
+        }
+
+        let mut builder = LogBuilder::new(
+            level_severity(*metadata.level()),
+            metadata.line().unwrap_or_default() as i64,
+            metadata.file().unwrap_or_default(),
+            metadata.module_path().unwrap_or_else(|| metadata.target()),
+        );
+        if let Some(scope) = ctx.event_scope(event) {
+            let mut operation = None;
+            for span in scope.from_root() {
+                if let Some(labels) = span.extensions().get::<SpanLabels>() {
+                    for (label, value) in &labels.0 {
+                        builder = builder.label(label, value);
+                    }
+                }
+                operation = Some(span.name());
+            }
+            if let Some(operation) = operation {
+                builder = builder.operation(operation);
+            }
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let message = fields.message.take().unwrap_or_default();
+        builder.payload = Some(if fields.values.is_empty() {
+            Payload::Text(message)
+        } else if self.fields_as_labels {
+            for (label, value) in fields.into_labels() {
+                builder = builder.label(label, value);
+            }
+            Payload::Text(message)
+        } else {
+            let mut payload = fields.values;
+            payload.insert("message".to_owned(), message.into());
+            Payload::Json(payload)
+        });
+        push(builder);
+    }
+}
+
+#[cfg(not(test))]
+fn push(builder: LogBuilder) {
+    builder.build_and_push();
+}
+
+/// Tests capture entries instead of writing them.
+#[cfg(test)]
+fn push(builder: LogBuilder) {
+    tests::CAPTURED.with(|captured| captured.borrow_mut().push(builder.build()));
+}
+
+fn level_severity(level: Level) -> LogSeverity {
+    match level {
+        Level::ERROR => LogSeverity::Error,
+        Level::WARN => LogSeverity::Warning,
+        Level::INFO => LogSeverity::Info,
+        _ => LogSeverity::Debug,
+    }
+}
+
+/// Collects `message` separately from other fields.
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Fields {
+    fn into_labels(self) -> BTreeMap<String, String> {
+        self.values
+            .into_iter()
+            .map(|(label, value)| match value {
+                serde_json::Value::String(value) => (label, value),
+                value => (label, value.to_string()),
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, field: &Field, value: serde_json::Value) {
+        self.values.insert(field.name().to_owned(), value);
+    }
+}
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_owned());
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{:?}", value);
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else {
+            self.insert(field, value.into());
+        }
+    }
+}
+
+// --------------------------------
+// LOG
+// --------------------------------
+
+/// Forwards records of the `log` crate to the logger queue.
+#[derive(Debug, Clone, Copy)]
+pub struct Logger {
+    level: log::LevelFilter,
+}
+
+impl Logger {
+    pub fn new(level: log::LevelFilter) -> Logger {
+        Logger { level }
+    }
+
+    /// Sets the logger as the global `log` logger.
+    pub fn install(self) -> crate::Result<()> {
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|_| crate::Error::AlreadyInitialized("log logger"))?;
+        log::set_max_level(self.level);
+        Ok(())
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.level() <= self.level && !is_ignored(metadata.target())
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let severity = match record.level() {
+            log::Level::Error => LogSeverity::Error,
+            log::Level::Warn => LogSeverity::Warning,
+            log::Level::Info => LogSeverity::Info,
+            log::Level::Debug | log::Level::Trace => LogSeverity::Debug,
+        };
+        LogBuilder::new(
+            severity,
+            record.line().unwrap_or_default() as i64,
+            record.file_static().unwrap_or_default(),
+            record.module_path_static().unwrap_or_default(),
+        )
+        .send_text(record.args().to_string());
+    }
+
+    /// Entries are written in background, await
+    /// [`flush`](super::flush) to wait for them.
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::google::logging::LogEntry;
+    use std::cell::RefCell;
+    use tracing_subscriber::{layer::Layered, prelude::*, Registry};
+
+    thread_local! {
+        pub(super) static CAPTURED: RefCell<Vec<LogEntry>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn capture(layer: LoggingLayer, f: impl FnOnce()) -> Vec<LogEntry> {
+        CAPTURED.with(|captured| captured.borrow_mut().clear());
+        tracing::subscriber::with_default(Registry::default().with(layer), f);
+        CAPTURED.with(|captured| captured.take())
+    }
+
+    fn text(entry: &LogEntry) -> &str {
+        match &entry.payload {
+            Payload::Text(text) => text,
+            Payload::Json(_) => panic!("expected text payload"),
+        }
+    }
+
+    #[test]
+    fn maps_level_to_severity() {
+        let entries = capture(LoggingLayer::new(), || {
+            tracing::error!("error");
+            tracing::warn!("warn");
+            tracing::info!("info");
+            tracing::debug!("debug");
+            tracing::trace!("trace");
+        });
+
+        let severities: Vec<_> = entries.iter().map(|e| e.severity.as_str()).collect();
+        assert_eq!(severities, ["ERROR", "WARNING", "INFO", "DEBUG", "DEBUG"]);
+        assert_eq!(text(&entries[0]), "error");
+    }
+
+    #[test]
+    fn event_fields_go_to_payload_or_labels() {
+        let entries = capture(LoggingLayer::new(), || tracing::info!(count = 3, "counted"));
+        match &entries[0].payload {
+            Payload::Json(json) => {
+                assert_eq!(json["message"], "counted");
+                assert_eq!(json["count"], 3);
+            }
+            Payload::Text(_) => panic!("expected json payload"),
+        }
+
+        let entries = capture(LoggingLayer::new().fields_as_labels(), || {
+            tracing::info!(count = 3, "counted")
+        });
+        assert_eq!(text(&entries[0]), "counted");
+        assert_eq!(entries[0].labels["count"], "3");
+    }
+
+    #[test]
+    fn span_fields_become_labels_and_innermost_span_the_operation() {
+        let entries = capture(LoggingLayer::new(), || {
+            let outer = tracing::info_span!("outer", tenant = "acme", user = 1);
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("inner", user = 2);
+            let _inner = inner.enter();
+            tracing::info!("nested");
+        });
+
+        let entry = &entries[0];
+        assert_eq!(entry.labels.len(), 2);
+        assert_eq!(entry.labels["tenant"], "acme");
+        assert_eq!(entry.labels["user"], "2");
+        assert_eq!(entry.operation.as_ref().unwrap().id, "inner");
+    }
+
+    #[test]
+    fn recorded_span_field_replaces_label() {
+        let entries = capture(LoggingLayer::new(), || {
+            let span = tracing::info_span!("request", user = tracing::field::Empty);
+            span.record("user", "first");
+            span.record("user", "second");
+            let _span = span.enter();
+
+            tracing::dispatcher::get_default(|dispatch| {
+                let subscriber = dispatch
+                    .downcast_ref::<Layered<LoggingLayer, Registry>>()
+                    .unwrap();
+                let span = subscriber.span(&span.id().unwrap()).unwrap();
+                let extensions = span.extensions();
+                assert_eq!(extensions.get::<SpanLabels>().unwrap().0.len(), 1);
+            });
+            tracing::info!("recorded");
+        });
+
+        assert_eq!(entries[0].labels["user"], "second");
+    }
+
+    #[test]
+    fn ignores_events_of_logger_dependencies() {
+        let entries = capture(LoggingLayer::new(), || {
+            tracing::info!(target: "hyper", "ignored");
+            tracing::info!(target: "h2::codec", "ignored");
+            tracing::info!(target: "tonic::transport::channel", "ignored");
+            tracing::info!(target: "hyperlocal", "kept");
+        });
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(text(&entries[0]), "kept");
+        assert!(!is_ignored("app::hyper"));
+    }
+}
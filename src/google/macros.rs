@@ -1,71 +1,160 @@
 #[macro_export]
 macro_rules! rpc_service {
-    ($domain_name: literal, $($scope: literal),+) => {
+    // fields after `;` are created with `Default::default()`
+    // and hold state of the client, shared by clones if wrapped in `Arc`
+    ($client: ident, $domain_name: literal, $($scope: literal),+ $(; $($(#[$meta: meta])* $field: ident: $field_type: ty),+)?) => {
         use crate::google::{auth};
         use once_cell::sync::OnceCell;
+        use std::sync::Arc;
         use tonic::{
-            metadata::MetadataValue,
+            metadata::{Ascii, MetadataValue},
             transport::{Channel, ClientTlsConfig},
-            Request,
         };
         use yup_oauth2::authenticator::DefaultAuthenticator;
 
         const DEFAULT_HOST: &str = concat!("https://", $domain_name, ".googleapis.com");
         const SCOPES: &[&str] = &[$($scope),+];
 
-        struct RpcService {
+        #[doc = concat!("Handle to the `", $domain_name, "` service.")]
+        ///
+        /// Clones share the underlying channel and authenticator,
+        /// so cloning is cheap.
+        #[derive(Clone)]
+        pub struct $client {
             channel: Channel,
-            auth: DefaultAuthenticator,
+            /// `None` for endpoints accepting requests without authorization.
+            auth: Option<Arc<DefaultAuthenticator>>,
+            $($($(#[$meta])* $field: $field_type,)+)?
         }
 
-        static SERVICE: OnceCell<RpcService> = OnceCell::new();
+        impl $client {
+            pub(crate) async fn new(
+                tls_config: ClientTlsConfig,
+                endpoint: Option<&str>,
+                key: Option<&str>,
+            ) -> crate::Result<$client> {
+                let channel =
+                    crate::rpc::connect(tls_config, endpoint.unwrap_or(DEFAULT_HOST)).await?;
+                let auth = match key {
+                    Some(key) => Some(Arc::new(auth(key, SCOPES).await?)),
+                    None => None,
+                };
+                Ok($client {
+                    channel,
+                    auth,
+                    $($($(#[$meta])* $field: Default::default(),)+)?
+                })
+            }
 
-        pub(crate) async fn initialize<'a>(
-            tls_config: ClientTlsConfig,
-            key: &str,
-        ) {
-            let channel = Channel::from_shared(DEFAULT_HOST)
-                .unwrap()
-                .tls_config(tls_config)
-                .unwrap()
-                .connect()
-                .await
-                .unwrap();
-            let auth = auth(key, SCOPES).await;
-            let inner = RpcService { channel, auth };
-            if SERVICE.set(inner).is_err() {
-                panic!(concat!("Already initialized ", $domain_name, " service"));
+            /// Value of `authorization` header, if the client has credentials.
+            async fn authorization(&self) -> crate::Result<Option<MetadataValue<Ascii>>> {
+                let auth = match &self.auth {
+                    Some(auth) => auth,
+                    None => return Ok(None),
+                };
+                let token = auth.token(SCOPES).await?;
+                let bearer_token = format!("Bearer {}", token.as_str());
+                Ok(Some(MetadataValue::from_str(&bearer_token)?))
             }
         }
+
+        static SERVICE: OnceCell<$client> = OnceCell::new();
+
+        /// Sets the client used by functions of the module.
+        pub(crate) fn install(client: $client) -> crate::Result<()> {
+            SERVICE
+                .set(client)
+                .map_err(|_| crate::Error::AlreadyInitialized($domain_name))
+        }
+
+        fn service() -> crate::Result<&'static $client> {
+            SERVICE
+                .get()
+                .ok_or(crate::Error::NotInitialized($domain_name))
+        }
     };
 }
 
 #[macro_export]
 macro_rules! rest_service {
-    ($domain_name: literal, $($scope: literal),+) => {
+    ($client: ident, $domain_name: literal, $($scope: literal),+) => {
         use crate::google::{auth};
         use once_cell::sync::OnceCell;
         use reqwest::Client;
+        use std::sync::Arc;
         use yup_oauth2::authenticator::DefaultAuthenticator;
 
+        const DEFAULT_HOST: &str = concat!("https://", $domain_name, ".googleapis.com");
         const SCOPES: &[&str] = &[$($scope),+];
 
-        struct RestService {
+        #[doc = concat!("Handle to the `", $domain_name, "` service.")]
+        ///
+        /// Clones share the underlying http client and authenticator,
+        /// so cloning is cheap.
+        #[derive(Clone)]
+        pub struct $client {
             client: Client,
-            auth: DefaultAuthenticator,
+            /// `None` for endpoints accepting requests without authorization.
+            auth: Option<Arc<DefaultAuthenticator>>,
+            /// Scheme and host requests are sent to, without trailing slash.
+            base_url: String,
         }
 
-        static SERVICE: OnceCell<RestService> = OnceCell::new();
+        impl $client {
+            pub(crate) async fn new(
+                endpoint: Option<&str>,
+                key: Option<&str>,
+            ) -> crate::Result<$client> {
+                let client = Client::builder()
+                    .timeout(std::time::Duration::from_secs(60))
+                    .build()?;
+                let auth = match key {
+                    Some(key) => Some(Arc::new(auth(key, SCOPES).await?)),
+                    None => None,
+                };
+                let base_url = endpoint
+                    .unwrap_or(DEFAULT_HOST)
+                    .trim_end_matches('/')
+                    .to_owned();
+                Ok($client {
+                    client,
+                    auth,
+                    base_url,
+                })
+            }
 
-        pub(crate) async fn initialize<'a>(
-            key: &str,
-        ) {
-            let client = Client::builder().timeout(std::time::Duration::from_secs(60)).build().unwrap();
-            let auth = auth(key, SCOPES).await;
-            let inner = RestService { client, auth };
-            if SERVICE.set(inner).is_err() {
-                panic!(concat!("Already initialized ", $domain_name, " service"));
+            /// Headers authorizing a request, empty if the client has no credentials.
+            async fn auth_headers(&self) -> crate::Result<reqwest::header::HeaderMap> {
+                let mut headers = reqwest::header::HeaderMap::new();
+                if let Some(auth) = &self.auth {
+                    let token = auth.token(SCOPES).await?;
+                    let bearer_token = format!("Bearer {}", token.as_str());
+                    headers.insert(
+                        reqwest::header::AUTHORIZATION,
+                        reqwest::header::HeaderValue::from_str(&bearer_token)
+                            .map_err(|e| crate::Error::Auth(e.to_string()))?,
+                    );
+                }
+                Ok(headers)
             }
         }
+
+        static SERVICE: OnceCell<$client> = OnceCell::new();
+
+        pub(crate) async fn initialize(
+            endpoint: Option<&str>,
+            key: Option<&str>,
+        ) -> crate::Result<()> {
+            let client = $client::new(endpoint, key).await?;
+            SERVICE
+                .set(client)
+                .map_err(|_| crate::Error::AlreadyInitialized($domain_name))
+        }
+
+        fn service() -> crate::Result<&'static $client> {
+            SERVICE
+                .get()
+                .ok_or(crate::Error::NotInitialized($domain_name))
+        }
     };
 }
@@ -15,19 +15,62 @@ pub mod logging;
 #[cfg(feature = "google-spreadsheets")]
 pub mod spreadsheets;
 
+use crate::{Error, Result};
+use std::collections::HashMap;
+#[cfg(feature = "google-tts")]
+use std::sync::Arc;
 use tonic::transport::ClientTlsConfig;
 use yup_oauth2::{authenticator::DefaultAuthenticator, ServiceAccountAuthenticator};
 
+/// Service whose endpoint can be overridden with [`RpcBuilder::endpoint`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Service {
+    #[cfg(feature = "google-stt")]
+    Speech,
+    #[cfg(feature = "google-tts")]
+    TextToSpeech,
+    #[cfg(feature = "google-tasks")]
+    Tasks,
+    #[cfg(feature = "google-logging")]
+    Logging,
+    #[cfg(feature = "google-spreadsheets")]
+    Sheets,
+}
+
 pub struct RpcBuilder<'a> {
     tls_config: ClientTlsConfig,
     key: &'a str,
+    endpoints: HashMap<Service, String>,
+    without_auth: bool,
+    #[cfg(feature = "google-tts")]
+    tts_cache: Option<Arc<tts::SynthesisCache>>,
 }
 
 macro_rules! initialize_fn {
-    ($name: ident, $fun_name: ident) => {
-        pub async fn $fun_name(self) -> RpcBuilder<'a> {
-            $name::initialize(self.tls_config.clone(), self.key).await;
-            self
+    ($name: ident, $client_fn: ident, $fun_name: ident) => {
+        pub async fn $fun_name(self) -> Result<RpcBuilder<'a>> {
+            $name::install(self.$client_fn().await?)?;
+            Ok(self)
+        }
+    };
+}
+
+#[cfg(any(
+    feature = "google-stt",
+    feature = "google-tasks",
+    feature = "google-logging"
+))]
+macro_rules! client_fn {
+    ($name: ident, $client: ident, $service: ident, $fun_name: ident, $doc: literal) => {
+        #[doc = $doc]
+        pub async fn $fun_name(&self) -> Result<$name::$client> {
+            let endpoint = self.endpoints.get(&Service::$service).map(|x| x.as_str());
+            $name::$client::new(
+                self.tls_config.clone(),
+                endpoint,
+                self.key(Service::$service),
+            )
+            .await
         }
     };
 }
@@ -41,34 +84,124 @@ impl<'a> RpcBuilder<'a> {
         tls_config.set_protocols(&["h2".into()]);
         let tls_config = ClientTlsConfig::new().rustls_client_config(tls_config);
 
-        RpcBuilder { tls_config, key }
+        RpcBuilder {
+            tls_config,
+            key,
+            endpoints: HashMap::new(),
+            without_auth: false,
+            #[cfg(feature = "google-tts")]
+            tts_cache: None,
+        }
+    }
+
+    /// Overrides the endpoint of the given service, for example to target
+    /// a local emulator. Endpoints starting with `http://` are connected
+    /// to without tls.
+    pub fn endpoint(mut self, service: Service, endpoint: impl Into<String>) -> RpcBuilder<'a> {
+        self.endpoints.insert(service, endpoint.into());
+        self
+    }
+
+    /// Sends requests to overridden endpoints without authorization, so that
+    /// emulators need neither credentials nor network access. Services left
+    /// on their default endpoints still authorize with the key.
+    pub fn without_auth(mut self) -> RpcBuilder<'a> {
+        self.without_auth = true;
+        self
+    }
+
+    /// Makes text-to-speech clients reuse results of identical requests.
+    /// Clients created by the builder share the cache, clients
+    /// of other builders do not.
+    #[cfg(feature = "google-tts")]
+    pub fn tts_cache(mut self, cache: tts::SynthesisCache) -> RpcBuilder<'a> {
+        self.tts_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Key for the service, `None` if its requests go without authorization.
+    fn key(&self, service: Service) -> Option<&'a str> {
+        if self.without_auth && self.endpoints.contains_key(&service) {
+            None
+        } else {
+            Some(self.key)
+        }
+    }
+
+    #[cfg(feature = "google-stt")]
+    initialize_fn!(stt, stt_client, initialize_stt);
+    #[cfg(feature = "google-tts")]
+    initialize_fn!(tts, tts_client, initialize_tts);
+    #[cfg(feature = "google-tasks")]
+    initialize_fn!(tasks, tasks_client, initialize_tasks);
+    #[cfg(feature = "google-logging")]
+    initialize_fn!(logging, logging_client, initialize_logging);
+    #[cfg(feature = "google-spreadsheets")]
+    pub async fn initialize_spreadsheets(self) -> Result<RpcBuilder<'a>> {
+        let endpoint = self.endpoints.get(&Service::Sheets).map(|x| x.as_str());
+        spreadsheets::initialize(endpoint, self.key(Service::Sheets)).await?;
+        Ok(self)
     }
 
     #[cfg(feature = "google-stt")]
-    initialize_fn!(stt, initialize_stt);
+    client_fn!(
+        stt,
+        SpeechClient,
+        Speech,
+        stt_client,
+        "Connects a speech client authorized with the key of the builder."
+    );
+    /// Connects a text-to-speech client, e.g. for voices of another project.
+    /// Results are reused through the cache set by [`tts_cache`](Self::tts_cache).
     #[cfg(feature = "google-tts")]
-    initialize_fn!(tts, initialize_tts);
+    pub async fn tts_client(&self) -> Result<tts::TtsClient> {
+        let endpoint = self
+            .endpoints
+            .get(&Service::TextToSpeech)
+            .map(|x| x.as_str());
+        let client = tts::TtsClient::new(
+            self.tls_config.clone(),
+            endpoint,
+            self.key(Service::TextToSpeech),
+        )
+        .await?;
+        Ok(client.with_cache(self.tts_cache.clone()))
+    }
     #[cfg(feature = "google-tasks")]
-    initialize_fn!(tasks, initialize_tasks);
+    client_fn!(
+        tasks,
+        TasksClient,
+        Tasks,
+        tasks_client,
+        "Connects a client for queues the service account of the builder can reach."
+    );
     #[cfg(feature = "google-logging")]
-    initialize_fn!(logging, initialize_logging);
+    client_fn!(
+        logging,
+        LoggingClient,
+        Logging,
+        logging_client,
+        "Connects a logging client. The background logger keeps writing \
+        through the instance set up by `initialize_logging`."
+    );
+    /// Creates a Sheets client with its own http client and token.
     #[cfg(feature = "google-spreadsheets")]
-    pub async fn initialize_spreadsheets(self) -> RpcBuilder<'a> {
-        spreadsheets::initialize(self.key).await;
-        self
+    pub async fn spreadsheets_client(&self) -> Result<spreadsheets::SheetsClient> {
+        let endpoint = self.endpoints.get(&Service::Sheets).map(|x| x.as_str());
+        spreadsheets::SheetsClient::new(endpoint, self.key(Service::Sheets)).await
     }
 }
 
-async fn auth(key: &str, scopes: &[&str]) -> DefaultAuthenticator {
-    let key = serde_json::from_str(key).unwrap();
+async fn auth(key: &str, scopes: &[&str]) -> Result<DefaultAuthenticator> {
+    let key = serde_json::from_str(key).map_err(|e| Error::Auth(e.to_string()))?;
 
     let auth = ServiceAccountAuthenticator::builder(key)
         .build()
         .await
-        .unwrap();
+        .map_err(|e| Error::Auth(e.to_string()))?;
 
     // Беру токен, чтобы прогреть, по возможности.
     // Плюс если появятся какие то ошибки, то они будут видны на старте
-    let _ = auth.token(scopes).await.unwrap();
-    auth
+    let _ = auth.token(scopes).await?;
+    Ok(auth)
 }
@@ -1,30 +1,23 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::fmt::Display;
 
-use crate::google::auth;
-use once_cell::sync::OnceCell;
-use reqwest::Client;
-use yup_oauth2::authenticator::DefaultAuthenticator;
-
-const SCOPES: &[&str] = &["https://www.googleapis.com/auth/spreadsheets"];
-
-struct RestService {
-    client: Client,
-    auth: DefaultAuthenticator,
-}
-
-static SERVICE: OnceCell<RestService> = OnceCell::new();
-
-pub(crate) async fn initialize<'a>(key: &str) {
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(60))
-        .build()
-        .unwrap();
-    let auth = auth(key, SCOPES).await;
-    let inner = RestService { client, auth };
-    if SERVICE.set(inner).is_err() {
-        panic!(concat!("Already initialized sheets service"));
+use crate::Error;
+
+crate::rest_service!(
+    SheetsClient,
+    "sheets",
+    "https://www.googleapis.com/auth/spreadsheets"
+);
+
+async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> crate::Result<T> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::Http {
+            status: status.as_u16(),
+            body: response.text().await.unwrap_or_default(),
+        });
     }
+    Ok(response.json().await?)
 }
 
 pub struct Range {
@@ -281,61 +274,8 @@ pub struct GetParams<'a> {
 }
 
 /// Returns a range of values from a spreadsheet. The caller must specify the spreadsheet ID and a range.
-pub async fn get<'a>(params: GetParams<'_>) -> Result<ValueRange, String> {
-    // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}
-    let mut query_params = Vec::with_capacity(6);
-
-    query_params.push((
-        "majorDimension",
-        params.major_dimension.unwrap_or_default().to_string(),
-    ));
-    query_params.push((
-        "valueRenderOption",
-        params.value_render_option.unwrap_or_default().to_string(),
-    ));
-    query_params.push((
-        "dateTimeRenderOption",
-        params
-            .date_time_render_option
-            .unwrap_or_default()
-            .to_string(),
-    ));
-    query_params.push(("alt", "json".to_string()));
-
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}",
-        params.spreadsheet_id,
-        params.range.to_string()
-    );
-    let url = reqwest::Url::parse_with_params(&url, &query_params).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .get(url)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
-        }
-        Err(err) => Err(err.to_string()),
-    }
+pub async fn get(params: GetParams<'_>) -> crate::Result<ValueRange> {
+    service()?.get(params).await
 }
 
 pub struct UpdateParams<'a> {
@@ -391,73 +331,8 @@ pub struct UpdateValuesResponse {
 
 /// Sets values in a range of a spreadsheet. The caller must specify the
 /// spreadsheet ID, range, and a valueInputOption.
-pub async fn update<'a>(params: UpdateParams<'_>) -> Result<UpdateValuesResponse, String> {
-    // PUT https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}
-    let mut query_params = Vec::with_capacity(6);
-
-    query_params.push((
-        "valueInputOption",
-        params.value_input_option.unwrap_or_default().to_string(),
-    ));
-    query_params.push((
-        "includeValuesInResponse",
-        params
-            .include_values_in_response
-            .unwrap_or_default()
-            .to_string(),
-    ));
-    query_params.push((
-        "responseDateTimeRenderOption",
-        params
-            .response_date_time_render_option
-            .unwrap_or_default()
-            .to_string(),
-    ));
-    query_params.push((
-        "responseValueRenderOption",
-        params
-            .response_value_render_option
-            .unwrap_or_default()
-            .to_string(),
-    ));
-    query_params.push(("alt", "json".to_string()));
-
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}",
-        params.spreadsheet_id,
-        params.range.to_string()
-    );
-
-    let url = reqwest::Url::parse_with_params(&url, &query_params).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .put(url)
-        .json(&params.values)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
-        }
-        Err(err) => Err(err.to_string()),
-    }
+pub async fn update(params: UpdateParams<'_>) -> crate::Result<UpdateValuesResponse> {
+    service()?.update(params).await
 }
 
 pub struct AppendParams<'a> {
@@ -515,77 +390,8 @@ pub struct AppendValuesResponse {
 /// and a valueInputOption. The valueInputOption only controls
 /// how the input data will be added to the sheet (column-wise or row-wise),
 /// it does not influence what cell the data starts being written to.
-pub async fn append<'a>(params: AppendParams<'_>) -> Result<AppendValuesResponse, String> {
-    // POST https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}:append
-    let query_params = vec![
-        (
-            "valueInputOption",
-            params.value_input_option.unwrap_or_default().to_string(),
-        ),
-        (
-            "includeValuesInResponse",
-            params
-                .include_values_in_response
-                .unwrap_or_default()
-                .to_string(),
-        ),
-        (
-            "insertDataOption",
-            params.insert_data_option.unwrap_or_default().to_string(),
-        ),
-        (
-            "responseDateTimeRenderOption",
-            params
-                .response_date_time_render_option
-                .unwrap_or_default()
-                .to_string(),
-        ),
-        (
-            "responseValueRenderOption",
-            params
-                .response_value_render_option
-                .unwrap_or_default()
-                .to_string(),
-        ),
-        ("alt", "json".to_string()),
-    ];
-
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}:append",
-        params.spreadsheet_id,
-        params.range.to_string()
-    );
-
-    let url = reqwest::Url::parse_with_params(&url, &query_params).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .post(url)
-        .json(&params.values)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
-        }
-        Err(err) => Err(err.to_string()),
-    }
+pub async fn append(params: AppendParams<'_>) -> crate::Result<AppendValuesResponse> {
+    service()?.append(params).await
 }
 
 pub struct BatchGetParams<'a> {
@@ -628,64 +434,8 @@ pub struct BatchGetValuesResponse {
 
 /// Returns one or more ranges of values from a spreadsheet.
 /// The caller must specify the spreadsheet ID and one or more ranges.
-pub async fn batch_get<'a>(params: BatchGetParams<'_>) -> Result<BatchGetValuesResponse, String> {
-    // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values:batchGet
-    let mut query_params = Vec::with_capacity(4 + params.ranges.len());
-
-    for range in params.ranges {
-        query_params.push(("ranges", range.to_string()));
-    }
-
-    query_params.push((
-        "majorDimension",
-        params.major_dimension.unwrap_or_default().to_string(),
-    ));
-    query_params.push((
-        "dateTimeRenderOption",
-        params
-            .date_time_render_option
-            .unwrap_or_default()
-            .to_string(),
-    ));
-    query_params.push((
-        "valueRenderOption",
-        params.value_render_option.unwrap_or_default().to_string(),
-    ));
-    query_params.push(("alt", "json".to_string()));
-
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values:batchGet",
-        params.spreadsheet_id
-    );
-    let url = reqwest::Url::parse_with_params(&url, &query_params).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .get(url)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
-        }
-        Err(err) => Err(err.to_string()),
-    }
+pub async fn batch_get(params: BatchGetParams<'_>) -> crate::Result<BatchGetValuesResponse> {
+    service()?.batch_get(params).await
 }
 
 #[derive(Deserialize)]
@@ -709,41 +459,8 @@ pub struct Spreadsheet {
     // https://developers.google.com/sheets/api/reference/rest/v4/spreadsheets#Spreadsheet
 }
 
-pub async fn get_spreadsheet_info(spreadsheet_id: &str) -> Result<Spreadsheet, String> {
-    // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}",
-        spreadsheet_id
-    );
-    let url = reqwest::Url::parse(&url).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .get(url)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
-        }
-        Err(err) => Err(err.to_string()),
-    }
+pub async fn get_spreadsheet_info(spreadsheet_id: &str) -> crate::Result<Spreadsheet> {
+    service()?.get_spreadsheet_info(spreadsheet_id).await
 }
 
 #[derive(Serialize)]
@@ -782,42 +499,251 @@ pub struct BatchUpdateResponse {
 
 /// Sets values in one or more ranges of a spreadsheet.
 /// The caller must specify the spreadsheet ID, a valueInputOption, and one or more ValueRanges.
-pub async fn batch_update<'a>(
-    params: BatchUpdateParams<'_>,
-) -> Result<BatchUpdateResponse, String> {
-    // POST https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values:batchUpdate
-    let url = format!(
-        "https://sheets.googleapis.com/v4/spreadsheets/{}/values:batchUpdate",
-        params.spreadsheet_id
-    );
-    let url = reqwest::Url::parse(&url).unwrap();
-
-    let service = SERVICE.get().unwrap();
-    let token = service.auth.token(SCOPES).await.unwrap();
-
-    let result = service
-        .client
-        .post(url)
-        .json(&params)
-        .bearer_auth(token.as_str())
-        .send()
-        .await;
-
-    match result {
-        Ok(result) => {
-            if !result.status().is_success() {
-                return Err(format!(
-                    "Unexpected result with status code {}: {}",
-                    result.status(),
-                    result.text().await.unwrap()
-                ));
-            }
-            let result = result.json().await;
-            match result {
-                Ok(result) => Ok(result),
-                Err(err) => Err(err.to_string()),
-            }
+pub async fn batch_update(params: BatchUpdateParams<'_>) -> crate::Result<BatchUpdateResponse> {
+    service()?.batch_update(params).await
+}
+
+impl SheetsClient {
+    /// Returns a range of values from a spreadsheet. The caller must specify the spreadsheet ID and a range.
+    pub async fn get(&self, params: GetParams<'_>) -> crate::Result<ValueRange> {
+        // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}
+        let mut query_params = Vec::with_capacity(6);
+
+        query_params.push((
+            "majorDimension",
+            params.major_dimension.unwrap_or_default().to_string(),
+        ));
+        query_params.push((
+            "valueRenderOption",
+            params.value_render_option.unwrap_or_default().to_string(),
+        ));
+        query_params.push((
+            "dateTimeRenderOption",
+            params
+                .date_time_render_option
+                .unwrap_or_default()
+                .to_string(),
+        ));
+        query_params.push(("alt", "json".to_string()));
+
+        let url = format!(
+            "{}/v4/spreadsheets/{}/values/{}",
+            self.base_url,
+            params.spreadsheet_id,
+            params.range.to_string()
+        );
+        let url = reqwest::Url::parse_with_params(&url, &query_params)
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self.client.get(url).headers(headers).send().await?;
+
+        parse_response(result).await
+    }
+
+    /// Sets values in a range of a spreadsheet. The caller must specify the
+    /// spreadsheet ID, range, and a valueInputOption.
+    pub async fn update(&self, params: UpdateParams<'_>) -> crate::Result<UpdateValuesResponse> {
+        // PUT https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}
+        let mut query_params = Vec::with_capacity(6);
+
+        query_params.push((
+            "valueInputOption",
+            params.value_input_option.unwrap_or_default().to_string(),
+        ));
+        query_params.push((
+            "includeValuesInResponse",
+            params
+                .include_values_in_response
+                .unwrap_or_default()
+                .to_string(),
+        ));
+        query_params.push((
+            "responseDateTimeRenderOption",
+            params
+                .response_date_time_render_option
+                .unwrap_or_default()
+                .to_string(),
+        ));
+        query_params.push((
+            "responseValueRenderOption",
+            params
+                .response_value_render_option
+                .unwrap_or_default()
+                .to_string(),
+        ));
+        query_params.push(("alt", "json".to_string()));
+
+        let url = format!(
+            "{}/v4/spreadsheets/{}/values/{}",
+            self.base_url,
+            params.spreadsheet_id,
+            params.range.to_string()
+        );
+
+        let url = reqwest::Url::parse_with_params(&url, &query_params)
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self
+            .client
+            .put(url)
+            .json(&params.values)
+            .headers(headers)
+            .send()
+            .await?;
+
+        parse_response(result).await
+    }
+
+    /// Appends values to a spreadsheet. The input range is used to search for existing data
+    /// and find a "table" within that range. Values will be appended to the next
+    /// row of the table, starting with the first column of the table.
+    /// See the guide and sample code for specific details of how tables are detected and data is appended.
+    ///
+    /// The caller must specify the spreadsheet ID, range,
+    /// and a valueInputOption. The valueInputOption only controls
+    /// how the input data will be added to the sheet (column-wise or row-wise),
+    /// it does not influence what cell the data starts being written to.
+    pub async fn append(&self, params: AppendParams<'_>) -> crate::Result<AppendValuesResponse> {
+        // POST https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values/{range}:append
+        let query_params = vec![
+            (
+                "valueInputOption",
+                params.value_input_option.unwrap_or_default().to_string(),
+            ),
+            (
+                "includeValuesInResponse",
+                params
+                    .include_values_in_response
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            (
+                "insertDataOption",
+                params.insert_data_option.unwrap_or_default().to_string(),
+            ),
+            (
+                "responseDateTimeRenderOption",
+                params
+                    .response_date_time_render_option
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            (
+                "responseValueRenderOption",
+                params
+                    .response_value_render_option
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            ("alt", "json".to_string()),
+        ];
+
+        let url = format!(
+            "{}/v4/spreadsheets/{}/values/{}:append",
+            self.base_url,
+            params.spreadsheet_id,
+            params.range.to_string()
+        );
+
+        let url = reqwest::Url::parse_with_params(&url, &query_params)
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self
+            .client
+            .post(url)
+            .json(&params.values)
+            .headers(headers)
+            .send()
+            .await?;
+
+        parse_response(result).await
+    }
+
+    /// Returns one or more ranges of values from a spreadsheet.
+    /// The caller must specify the spreadsheet ID and one or more ranges.
+    pub async fn batch_get(
+        &self,
+        params: BatchGetParams<'_>,
+    ) -> crate::Result<BatchGetValuesResponse> {
+        // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values:batchGet
+        let mut query_params = Vec::with_capacity(4 + params.ranges.len());
+
+        for range in params.ranges {
+            query_params.push(("ranges", range.to_string()));
         }
-        Err(err) => Err(err.to_string()),
+
+        query_params.push((
+            "majorDimension",
+            params.major_dimension.unwrap_or_default().to_string(),
+        ));
+        query_params.push((
+            "dateTimeRenderOption",
+            params
+                .date_time_render_option
+                .unwrap_or_default()
+                .to_string(),
+        ));
+        query_params.push((
+            "valueRenderOption",
+            params.value_render_option.unwrap_or_default().to_string(),
+        ));
+        query_params.push(("alt", "json".to_string()));
+
+        let url = format!(
+            "{}/v4/spreadsheets/{}/values:batchGet",
+            self.base_url, params.spreadsheet_id
+        );
+        let url = reqwest::Url::parse_with_params(&url, &query_params)
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self.client.get(url).headers(headers).send().await?;
+
+        parse_response(result).await
+    }
+
+    pub async fn get_spreadsheet_info(&self, spreadsheet_id: &str) -> crate::Result<Spreadsheet> {
+        // GET https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}
+        let url = format!("{}/v4/spreadsheets/{}", self.base_url, spreadsheet_id);
+        let url = reqwest::Url::parse(&url).map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self.client.get(url).headers(headers).send().await?;
+
+        parse_response(result).await
+    }
+
+    /// Sets values in one or more ranges of a spreadsheet.
+    /// The caller must specify the spreadsheet ID, a valueInputOption, and one or more ValueRanges.
+    pub async fn batch_update(
+        &self,
+        params: BatchUpdateParams<'_>,
+    ) -> crate::Result<BatchUpdateResponse> {
+        // POST https://sheets.googleapis.com/v4/spreadsheets/{spreadsheetId}/values:batchUpdate
+        let url = format!(
+            "{}/v4/spreadsheets/{}/values:batchUpdate",
+            self.base_url, params.spreadsheet_id
+        );
+        let url = reqwest::Url::parse(&url).map_err(|e| Error::Transport(Box::new(e)))?;
+
+        let headers = self.auth_headers().await?;
+
+        let result = self
+            .client
+            .post(url)
+            .json(&params)
+            .headers(headers)
+            .send()
+            .await?;
+
+        parse_response(result).await
     }
 }
@@ -1,12 +1,73 @@
-crate::rpc_service!("speech", "https://www.googleapis.com/auth/cloud-platform");
-pub use super::generated::google::cloud::speech::v1::RecognitionConfig;
+crate::rpc_service!(
+    SpeechClient,
+    "speech",
+    "https://www.googleapis.com/auth/cloud-platform"
+);
 use super::generated::google::cloud::speech::v1::*;
+pub use super::generated::google::cloud::speech::v1::{
+    recognition_config::AudioEncoding, streaming_recognize_response::SpeechEventType,
+    LongRunningRecognizeResponse, RecognitionConfig, SpeechRecognitionAlternative,
+    SpeechRecognitionResult, StreamingRecognitionConfig, StreamingRecognitionResult,
+    StreamingRecognizeResponse, WordInfo,
+};
+use super::generated::google::longrunning::{
+    operation, operations_client::OperationsClient, GetOperationRequest, Operation,
+};
+use prost::Message;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Audio to be recognized.
+pub enum Audio {
+    /// Google Cloud Storage uri in form of `gs://bucket_name/object_name`.
+    Uri(String),
+    /// Raw audio bytes.
+    Content(Vec<u8>),
+}
+
+impl From<Vec<u8>> for Audio {
+    fn from(val: Vec<u8>) -> Self {
+        Audio::Content(val)
+    }
+}
+
+impl Audio {
+    /// Strips WAV header of inline LINEAR16 audio and takes sample rate
+    /// and channel count from it. Other encodings are passed as is.
+    fn prepare(self, config: &mut RecognitionConfig) -> crate::Result<RecognitionAudio> {
+        let linear = config.encoding == AudioEncoding::Linear16 as i32
+            || config.encoding == AudioEncoding::EncodingUnspecified as i32;
+        let content = match self {
+            Audio::Content(content) if linear => content,
+            audio => return Ok(audio.into()),
+        };
+
+        let pcm = crate::audio::detect_pcm(&content)?;
+        let format = match pcm.format {
+            Some(format) => format,
+            None => return Ok(Audio::Content(content).into()),
+        };
+        config.encoding = AudioEncoding::Linear16 as i32;
+        config.sample_rate_hertz = format.sample_rate as i32;
+        config.audio_channel_count = format.channels as i32;
+        Ok(Audio::Content(pcm.data.to_vec()).into())
+    }
+}
+
+impl From<Audio> for RecognitionAudio {
+    fn from(val: Audio) -> Self {
+        RecognitionAudio {
+            audio_source: Some(match val {
+                Audio::Uri(uri) => recognition_audio::AudioSource::Uri(uri),
+                Audio::Content(content) => recognition_audio::AudioSource::Content(content),
+            }),
+        }
+    }
+}
 
 fn default_config() -> RecognitionConfig {
     RecognitionConfig {
-        // encoding: Linear16
-        encoding: 1,
-        // FIXME pass format
+        encoding: AudioEncoding::Linear16 as i32,
         sample_rate_hertz: 8000,
         audio_channel_count: 1,
         enable_separate_recognition_per_channel: false,
@@ -25,49 +86,872 @@ fn default_config() -> RecognitionConfig {
     }
 }
 
-pub async fn recognize(uri: String, config: Option<RecognitionConfig>) -> Option<String> {
-    let service = SERVICE.get().unwrap();
-    let config = config.unwrap_or_else(default_config);
-    // --------------------------------
-    // construct request
-    // --------------------------------
-    let request = RecognizeRequest {
-        config: Some(config),
-        audio: Some(RecognitionAudio {
-            audio_source: Some(recognition_audio::AudioSource::Uri(uri)),
-        }),
-    };
-
-    // --------------------------------
-    // retrieve token and construct channel
-    // --------------------------------
-    let channel = service.channel.clone();
-    let token = service.auth.token(SCOPES).await.unwrap();
-    let bearer_token = format!("Bearer {}", token.as_str());
-    let token = MetadataValue::from_str(&bearer_token).unwrap();
-
-    let mut service =
-        speech_client::SpeechClient::with_interceptor(channel, move |mut req: Request<()>| {
-            let token = token.clone();
-            req.metadata_mut().insert("authorization", token);
-            Ok(req)
+/// Model to use for recognition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecognitionModel {
+    /// Best for short queries such as voice commands or voice search.
+    CommandAndSearch,
+    /// Best for audio that originated from a phone call.
+    PhoneCall,
+    /// Best for audio that originated from video or includes multiple speakers.
+    Video,
+    /// Best for audio that is not one of the specific audio models.
+    Default,
+}
+
+impl RecognitionModel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RecognitionModel::CommandAndSearch => "command_and_search",
+            RecognitionModel::PhoneCall => "phone_call",
+            RecognitionModel::Video => "video",
+            RecognitionModel::Default => "default",
+        }
+    }
+}
+
+/// Builder of [`RecognitionConfig`]. Starts from the same defaults
+/// which are used when no config is passed: LINEAR16, 8000 Hz, mono, `ru`.
+pub struct RecognitionConfigBuilder {
+    config: RecognitionConfig,
+}
+
+impl Default for RecognitionConfigBuilder {
+    fn default() -> Self {
+        RecognitionConfigBuilder {
+            config: default_config(),
+        }
+    }
+}
+
+impl RecognitionConfigBuilder {
+    pub fn new() -> RecognitionConfigBuilder {
+        Default::default()
+    }
+
+    pub fn encoding(mut self, encoding: AudioEncoding) -> RecognitionConfigBuilder {
+        self.config.encoding = encoding as i32;
+        self
+    }
+
+    pub fn sample_rate_hertz(mut self, sample_rate_hertz: i32) -> RecognitionConfigBuilder {
+        self.config.sample_rate_hertz = sample_rate_hertz;
+        self
+    }
+
+    pub fn audio_channel_count(mut self, audio_channel_count: i32) -> RecognitionConfigBuilder {
+        self.config.audio_channel_count = audio_channel_count;
+        self
+    }
+
+    /// Recognize each channel separately, results are marked with `channel_tag`.
+    pub fn separate_recognition_per_channel(mut self, enable: bool) -> RecognitionConfigBuilder {
+        self.config.enable_separate_recognition_per_channel = enable;
+        self
+    }
+
+    /// [BCP-47](https://www.rfc-editor.org/rfc/bcp/bcp47.txt) language tag,
+    /// for example `"en-US"`.
+    pub fn language_code(mut self, language_code: impl Into<String>) -> RecognitionConfigBuilder {
+        self.config.language_code = language_code.into();
+        self
+    }
+
+    /// Maximum number of alternatives returned for each result.
+    pub fn max_alternatives(mut self, max_alternatives: i32) -> RecognitionConfigBuilder {
+        self.config.max_alternatives = max_alternatives;
+        self
+    }
+
+    pub fn profanity_filter(mut self, enable: bool) -> RecognitionConfigBuilder {
+        self.config.profanity_filter = enable;
+        self
+    }
+
+    /// Words and phrases which are likely to be spoken,
+    /// so that recognizer prefers them. Can be called multiple times.
+    pub fn phrase_hints<P>(mut self, phrases: P) -> RecognitionConfigBuilder
+    where
+        P: IntoIterator,
+        P::Item: Into<String>,
+    {
+        self.config.speech_contexts.push(SpeechContext {
+            phrases: phrases.into_iter().map(Into::into).collect(),
+        });
+        self
+    }
+
+    /// Return start and end time of every recognized word.
+    pub fn word_time_offsets(mut self, enable: bool) -> RecognitionConfigBuilder {
+        self.config.enable_word_time_offsets = enable;
+        self
+    }
+
+    pub fn automatic_punctuation(mut self, enable: bool) -> RecognitionConfigBuilder {
+        self.config.enable_automatic_punctuation = enable;
+        self
+    }
+
+    /// Tag every recognized word with a speaker.
+    pub fn speaker_diarization(
+        mut self,
+        min_speaker_count: i32,
+        max_speaker_count: i32,
+    ) -> RecognitionConfigBuilder {
+        self.config.diarization_config = Some(SpeakerDiarizationConfig {
+            enable_speaker_diarization: true,
+            min_speaker_count,
+            max_speaker_count,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn model(mut self, model: RecognitionModel) -> RecognitionConfigBuilder {
+        self.config.model = model.as_str().to_owned();
+        self
+    }
+
+    /// Use enhanced model if it exists for the selected model and language.
+    pub fn use_enhanced(mut self, enable: bool) -> RecognitionConfigBuilder {
+        self.config.use_enhanced = enable;
+        self
+    }
+
+    pub fn build(self) -> RecognitionConfig {
+        self.config
+    }
+}
+
+impl From<RecognitionConfigBuilder> for RecognitionConfig {
+    fn from(val: RecognitionConfigBuilder) -> Self {
+        val.build()
+    }
+}
+
+fn default_streaming_config() -> StreamingRecognitionConfig {
+    StreamingRecognitionConfig {
+        config: Some(default_config()),
+        single_utterance: false,
+        interim_results: true,
+    }
+}
+
+impl StreamingRecognizeResponse {
+    /// Whether the service detected the end of the spoken utterance.
+    /// Sent only when `single_utterance` was requested, no more audio
+    /// is processed after this event.
+    pub fn is_end_of_utterance(&self) -> bool {
+        self.speech_event_type == SpeechEventType::EndOfSingleUtterance as i32
+    }
+}
+
+/// Recognition errors are reported inside of the message.
+fn streaming_result(
+    message: StreamingRecognizeResponse,
+) -> crate::Result<StreamingRecognizeResponse> {
+    match &message.error {
+        Some(error) => {
+            Err(tonic::Status::new(tonic::Code::from(error.code), error.message.clone()).into())
+        }
+        None => Ok(message),
+    }
+}
+
+/// Full recognition result.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    /// Most probable transcripts of all results joined with a space.
+    pub text: String,
+    /// Sequential results, each one corresponding to a consecutive
+    /// portion of the audio.
+    pub results: Vec<TranscriptResult>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptResult {
+    /// Alternatives ordered by accuracy, the most probable one comes first.
+    pub alternatives: Vec<TranscriptAlternative>,
+    /// Channel the result belongs to, when recognition per channel is enabled.
+    pub channel_tag: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptAlternative {
+    pub transcript: String,
+    /// Estimated confidence between 0.0 and 1.0. Set only for
+    /// the top alternative of final results, 0.0 otherwise.
+    pub confidence: f32,
+    /// Present only when `enable_word_time_offsets` or
+    /// speaker diarization is requested.
+    pub words: Vec<TranscriptWord>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptWord {
+    pub word: String,
+    /// Offset of the start of the word relative to the beginning of the audio.
+    pub start_time: Duration,
+    /// Offset of the end of the word relative to the beginning of the audio.
+    pub end_time: Duration,
+    /// Speaker of the word, present only when diarization is enabled.
+    pub speaker_tag: Option<i32>,
+}
+
+impl Transcript {
+    fn from_results(results: Vec<SpeechRecognitionResult>) -> Transcript {
+        let results: Vec<TranscriptResult> = results.into_iter().map(Into::into).collect();
+        let text = results
+            .iter()
+            .filter_map(|x| x.alternatives.first())
+            .map(|x| x.transcript.trim())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Transcript { text, results }
+    }
+}
+
+impl From<LongRunningRecognizeResponse> for Transcript {
+    fn from(val: LongRunningRecognizeResponse) -> Self {
+        Transcript::from_results(val.results)
+    }
+}
+
+impl From<SpeechRecognitionResult> for TranscriptResult {
+    fn from(val: SpeechRecognitionResult) -> Self {
+        TranscriptResult {
+            alternatives: val.alternatives.into_iter().map(Into::into).collect(),
+            channel_tag: val.channel_tag,
+        }
+    }
+}
+
+impl From<SpeechRecognitionAlternative> for TranscriptAlternative {
+    fn from(val: SpeechRecognitionAlternative) -> Self {
+        TranscriptAlternative {
+            transcript: val.transcript,
+            confidence: val.confidence,
+            words: val.words.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<WordInfo> for TranscriptWord {
+    fn from(val: WordInfo) -> Self {
+        TranscriptWord {
+            word: val.word,
+            start_time: val.start_time.map(to_std_duration).unwrap_or_default(),
+            end_time: val.end_time.map(to_std_duration).unwrap_or_default(),
+            // zero means that speaker is unknown
+            speaker_tag: Some(val.speaker_tag).filter(|x| *x != 0),
+        }
+    }
+}
+
+fn to_std_duration(val: prost_types::Duration) -> Duration {
+    Duration::new(val.seconds.max(0) as u64, val.nanos.max(0) as u32)
+}
+
+pub async fn recognize(
+    uri: String,
+    config: Option<RecognitionConfig>,
+) -> crate::Result<Option<String>> {
+    service()?.recognize(uri, config).await
+}
+
+/// Same as `recognize`, but accepts either uri or raw audio bytes.
+pub async fn recognize_audio(
+    audio: impl Into<Audio>,
+    config: Option<RecognitionConfig>,
+) -> crate::Result<Option<String>> {
+    service()?.recognize_audio(audio, config).await
+}
+
+/// Same as `recognize`, but keeps every result and alternative
+/// along with confidence, word timings and speaker tags.
+pub async fn recognize_transcript(
+    audio: impl Into<Audio>,
+    config: Option<RecognitionConfig>,
+) -> crate::Result<Transcript> {
+    service()?.recognize_transcript(audio, config).await
+}
+
+pub async fn streaming_recognize(
+    config: Option<StreamingRecognitionConfig>,
+) -> crate::Result<(
+    UnboundedSender<Vec<u8>>,
+    UnboundedReceiver<crate::Result<StreamingRecognizeResponse>>,
+)> {
+    service()?.streaming_recognize(config).await
+}
+
+/// Submits audio for asynchronous recognition. Use it for audio longer
+/// than one minute, which `recognize` does not accept.
+pub async fn long_running_recognize(
+    audio: impl Into<Audio>,
+    config: Option<RecognitionConfig>,
+) -> crate::Result<RecognitionOperation> {
+    service()?.long_running_recognize(audio, config).await
+}
+
+/// Handle to a submitted long running recognition.
+pub struct RecognitionOperation {
+    client: SpeechClient,
+    name: String,
+    poll_interval: Duration,
+    timeout: Option<Duration>,
+}
+
+impl RecognitionOperation {
+    /// Name of the operation assigned by the service.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How often `wait` asks the service about the operation. 5 seconds by default.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> RecognitionOperation {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long `wait` waits for the operation to complete. Unlimited by default.
+    pub fn timeout(mut self, timeout: Duration) -> RecognitionOperation {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Asks the service once about the operation,
+    /// returns `None` if it is not done yet.
+    pub async fn poll(&self) -> crate::Result<Option<LongRunningRecognizeResponse>> {
+        let token = self.client.authorization().await?;
+
+        let mut service = OperationsClient::with_interceptor(
+            self.client.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        let operation = service
+            .get_operation(GetOperationRequest {
+                name: self.name.clone(),
+            })
+            .await?
+            .into_inner();
+        operation_result(operation)
+    }
+
+    /// Polls the operation until it is done or timeout elapses.
+    pub async fn wait(self) -> crate::Result<LongRunningRecognizeResponse> {
+        let started = Instant::now();
+        loop {
+            if let Some(response) = self.poll().await? {
+                return Ok(response);
+            }
+            if let Some(timeout) = self.timeout {
+                if started.elapsed() + self.poll_interval > timeout {
+                    return Err(crate::Error::Timeout(timeout));
+                }
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+/// Response of the operation, `None` if it is not done yet.
+fn operation_result(operation: Operation) -> crate::Result<Option<LongRunningRecognizeResponse>> {
+    if !operation.done {
+        return Ok(None);
+    }
+
+    match operation.result {
+        Some(operation::Result::Response(any)) => Ok(Some(LongRunningRecognizeResponse::decode(
+            any.value.as_slice(),
+        )?)),
+        Some(operation::Result::Error(error)) => {
+            Err(tonic::Status::new(tonic::Code::from(error.code), error.message).into())
+        }
+        None => Err(crate::Error::Decode(
+            "operation is done but has no result".to_owned(),
+        )),
+    }
+}
+
+impl SpeechClient {
+    pub async fn recognize(
+        &self,
+        uri: String,
+        config: Option<RecognitionConfig>,
+    ) -> crate::Result<Option<String>> {
+        self.recognize_audio(Audio::Uri(uri), config).await
+    }
+
+    pub async fn recognize_audio(
+        &self,
+        audio: impl Into<Audio>,
+        config: Option<RecognitionConfig>,
+    ) -> crate::Result<Option<String>> {
+        let response = self.send_recognize(audio.into(), config).await?;
+
+        // --------------------------------
+        // take required result
+        // --------------------------------
+        Ok(response
+            .results
+            .first()
+            .and_then(|x| x.alternatives.first())
+            .map(|x| x.transcript.clone()))
+    }
+
+    pub async fn recognize_transcript(
+        &self,
+        audio: impl Into<Audio>,
+        config: Option<RecognitionConfig>,
+    ) -> crate::Result<Transcript> {
+        let response = self.send_recognize(audio.into(), config).await?;
+        Ok(Transcript::from_results(response.results))
+    }
+
+    async fn send_recognize(
+        &self,
+        audio: Audio,
+        config: Option<RecognitionConfig>,
+    ) -> crate::Result<RecognizeResponse> {
+        let mut config = config.unwrap_or_else(default_config);
+        // --------------------------------
+        // construct request
+        // --------------------------------
+        let audio = audio.prepare(&mut config)?;
+        let request = RecognizeRequest {
+            config: Some(config),
+            audio: Some(audio),
+        };
+
+        // --------------------------------
+        // retrieve token and construct channel
+        // --------------------------------
+        let token = self.authorization().await?;
+
+        let mut service = speech_client::SpeechClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        // --------------------------------
+        // send request
+        // --------------------------------
+        Ok(service.recognize(request).await?.into_inner())
+    }
+
+    pub async fn long_running_recognize(
+        &self,
+        audio: impl Into<Audio>,
+        config: Option<RecognitionConfig>,
+    ) -> crate::Result<RecognitionOperation> {
+        let mut config = config.unwrap_or_else(default_config);
+        let audio = audio.into().prepare(&mut config)?;
+        let request = LongRunningRecognizeRequest {
+            config: Some(config),
+            audio: Some(audio),
+        };
+
+        let token = self.authorization().await?;
+
+        let mut service = speech_client::SpeechClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        let operation = service.long_running_recognize(request).await?.into_inner();
+
+        Ok(RecognitionOperation {
+            client: self.clone(),
+            name: operation.name,
+            poll_interval: Duration::from_secs(5),
+            timeout: None,
+        })
+    }
+
+    pub async fn streaming_recognize(
+        &self,
+        config: Option<StreamingRecognitionConfig>,
+    ) -> crate::Result<(
+        UnboundedSender<Vec<u8>>,
+        UnboundedReceiver<crate::Result<StreamingRecognizeResponse>>,
+    )> {
+        let config = config.unwrap_or_else(default_streaming_config);
+
+        // --------------------------------
+        // retrieve token and construct channel
+        // --------------------------------
+        let token = self.authorization().await?;
+
+        let mut service = speech_client::SpeechClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        let (audio_sender, mut audio_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let stream = async_stream::stream! {
+            // config first
+            yield StreamingRecognizeRequest {
+                streaming_request: Some(
+                    streaming_recognize_request::StreamingRequest::StreamingConfig(config),
+                ),
+            };
+
+            while let Some(audio) = audio_receiver.recv().await {
+                yield StreamingRecognizeRequest {
+                    streaming_request: Some(
+                        streaming_recognize_request::StreamingRequest::AudioContent(audio),
+                    ),
+                };
+            }
+        };
+
+        let (result_sender, result_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut inner = match service.streaming_recognize(stream).await {
+                Ok(messages) => messages.into_inner(),
+                Err(status) => {
+                    let _ = result_sender.send(Err(status.into()));
+                    return;
+                }
+            };
+            loop {
+                let message = match inner.message().await {
+                    Ok(Some(message)) => streaming_result(message),
+                    Ok(None) => break,
+                    Err(status) => Err(status.into()),
+                };
+                let is_err = message.is_err();
+                // receiver was dropped, nobody is interested in results anymore
+                if result_sender.send(message).is_err() || is_err {
+                    break;
+                }
+            }
+        });
+
+        Ok((audio_sender, result_receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming_defaults() {
+        let config = default_streaming_config();
+        assert!(config.interim_results);
+        assert!(!config.single_utterance);
+        assert_eq!(config.config, Some(default_config()));
+    }
+
+    #[test]
+    fn streaming_error_in_message() {
+        let message = StreamingRecognizeResponse {
+            error: Some(crate::google::generated::google::rpc::Status {
+                code: tonic::Code::InvalidArgument as i32,
+                message: "bad audio".to_owned(),
+                details: vec![],
+            }),
+            ..Default::default()
+        };
+        match streaming_result(message) {
+            Err(crate::Error::Status(status)) => {
+                assert_eq!(status.code(), tonic::Code::InvalidArgument);
+                assert_eq!(status.message(), "bad audio");
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let message = StreamingRecognizeResponse {
+            speech_event_type: SpeechEventType::EndOfSingleUtterance as i32,
+            ..Default::default()
+        };
+        assert!(streaming_result(message).unwrap().is_end_of_utterance());
+        assert!(!StreamingRecognizeResponse::default().is_end_of_utterance());
+    }
+
+    #[test]
+    fn operation_not_done() {
+        let operation = Operation {
+            done: false,
+            ..Default::default()
+        };
+        assert!(operation_result(operation).unwrap().is_none());
+    }
+
+    #[test]
+    fn operation_done_with_response() {
+        let response = LongRunningRecognizeResponse {
+            results: vec![SpeechRecognitionResult {
+                alternatives: vec![SpeechRecognitionAlternative {
+                    transcript: "привет".to_owned(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let mut value = vec![];
+        response.encode(&mut value).unwrap();
+        let operation = Operation {
+            done: true,
+            result: Some(operation::Result::Response(prost_types::Any {
+                type_url: "type.googleapis.com/google.cloud.speech.v1.LongRunningRecognizeResponse"
+                    .to_owned(),
+                value,
+            })),
+            ..Default::default()
+        };
+        assert_eq!(operation_result(operation).unwrap(), Some(response));
+    }
+
+    #[test]
+    fn operation_done_with_error() {
+        let operation = Operation {
+            done: true,
+            result: Some(operation::Result::Error(
+                crate::google::generated::google::rpc::Status {
+                    code: tonic::Code::ResourceExhausted as i32,
+                    message: "quota".to_owned(),
+                    details: vec![],
+                },
+            )),
+            ..Default::default()
+        };
+        match operation_result(operation) {
+            Err(crate::Error::Status(status)) => {
+                assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+                assert_eq!(status.message(), "quota");
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let operation = Operation {
+            done: true,
+            result: None,
+            ..Default::default()
+        };
+        assert!(matches!(
+            operation_result(operation),
+            Err(crate::Error::Decode(_))
+        ));
+    }
+
+    fn word(word: &str, start: (i64, i32), end: (i64, i32), speaker_tag: i32) -> WordInfo {
+        let duration = |(seconds, nanos)| prost_types::Duration { seconds, nanos };
+        WordInfo {
+            word: word.to_owned(),
+            start_time: Some(duration(start)),
+            end_time: Some(duration(end)),
+            speaker_tag,
+        }
+    }
+
+    fn alternative(transcript: &str, confidence: f32) -> SpeechRecognitionAlternative {
+        SpeechRecognitionAlternative {
+            transcript: transcript.to_owned(),
+            confidence,
+            words: vec![],
+        }
+    }
+
+    #[test]
+    fn transcript_of_results() {
+        let results = vec![
+            SpeechRecognitionResult {
+                alternatives: vec![
+                    alternative("добрый день ", 0.9),
+                    alternative("добрый пень", 0.0),
+                ],
+                channel_tag: 1,
+            },
+            // results without alternatives are skipped in the text
+            SpeechRecognitionResult::default(),
+            SpeechRecognitionResult {
+                alternatives: vec![alternative(" как дела", 0.75)],
+                channel_tag: 2,
+            },
+        ];
+        let transcript = Transcript::from(LongRunningRecognizeResponse { results });
+
+        assert_eq!(transcript.text, "добрый день как дела");
+        assert_eq!(transcript.results.len(), 3);
+        let first = &transcript.results[0];
+        assert_eq!(first.channel_tag, 1);
+        let alternatives: Vec<(&str, f32)> = first
+            .alternatives
+            .iter()
+            .map(|x| (x.transcript.as_str(), x.confidence))
+            .collect();
+        assert_eq!(
+            alternatives,
+            vec![("добрый день ", 0.9), ("добрый пень", 0.0)]
+        );
+        assert!(transcript.results[1].alternatives.is_empty());
+        assert_eq!(transcript.results[2].channel_tag, 2);
+    }
+
+    #[test]
+    fn transcript_words_with_offsets() {
+        let mut result = alternative("раз два", 0.5);
+        result.words = vec![
+            word("раз", (0, 100_000_000), (0, 500_000_000), 1),
+            word("два", (1, 0), (1, 700_000_000), 0),
+        ];
+        let alternative = TranscriptAlternative::from(result);
+
+        let words: Vec<(&str, Duration, Duration, Option<i32>)> = alternative
+            .words
+            .iter()
+            .map(|x| (x.word.as_str(), x.start_time, x.end_time, x.speaker_tag))
+            .collect();
+        assert_eq!(
+            words,
+            vec![
+                (
+                    "раз",
+                    Duration::from_millis(100),
+                    Duration::from_millis(500),
+                    Some(1)
+                ),
+                // zero tag means diarization is off
+                (
+                    "два",
+                    Duration::from_secs(1),
+                    Duration::from_millis(1700),
+                    None
+                ),
+            ]
+        );
+
+        let missing_offsets = TranscriptWord::from(WordInfo {
+            word: "три".to_owned(),
+            ..Default::default()
         });
+        assert_eq!(missing_offsets.start_time, Duration::ZERO);
+        assert_eq!(missing_offsets.end_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn transcript_of_empty_response() {
+        let transcript = Transcript::from(LongRunningRecognizeResponse::default());
+        assert!(transcript.text.is_empty());
+        assert!(transcript.results.is_empty());
+    }
+
+    #[test]
+    fn builder_defaults() {
+        assert_eq!(RecognitionConfigBuilder::new().build(), default_config());
+        let config = RecognitionConfig::from(RecognitionConfigBuilder::new());
+        assert_eq!(config.encoding, AudioEncoding::Linear16 as i32);
+        assert_eq!(config.sample_rate_hertz, 8000);
+        assert_eq!(config.audio_channel_count, 1);
+        assert_eq!(config.language_code, "ru");
+        assert!(config.model.is_empty());
+        assert!(config.speech_contexts.is_empty());
+    }
+
+    #[test]
+    fn builder_output() {
+        let config = RecognitionConfigBuilder::new()
+            .encoding(AudioEncoding::OggOpus)
+            .sample_rate_hertz(48000)
+            .audio_channel_count(2)
+            .separate_recognition_per_channel(true)
+            .language_code("en-US")
+            .max_alternatives(3)
+            .phrase_hints(vec!["tomoru"])
+            .phrase_hints(["one", "two"].iter().copied())
+            .word_time_offsets(true)
+            .automatic_punctuation(true)
+            .speaker_diarization(2, 4)
+            .model(RecognitionModel::PhoneCall)
+            .use_enhanced(false)
+            .build();
 
-    // --------------------------------
-    // send request
-    // --------------------------------
-    let response = service
-        .recognize(Request::new(request))
-        .await
-        .unwrap()
-        .into_inner();
-
-    // --------------------------------
-    // take required result
-    // --------------------------------
-    response
-        .results
-        .get(0)
-        .and_then(|x| x.alternatives.get(0))
-        .map(|x| x.transcript.clone())
+        assert_eq!(config.encoding, AudioEncoding::OggOpus as i32);
+        assert_eq!(
+            (config.sample_rate_hertz, config.audio_channel_count),
+            (48000, 2)
+        );
+        assert!(config.enable_separate_recognition_per_channel);
+        assert_eq!(config.language_code, "en-US");
+        assert_eq!(config.max_alternatives, 3);
+        let phrases: Vec<&Vec<String>> =
+            config.speech_contexts.iter().map(|x| &x.phrases).collect();
+        assert_eq!(phrases, vec![&vec!["tomoru"], &vec!["one", "two"]]);
+        assert!(config.enable_word_time_offsets && config.enable_automatic_punctuation);
+        let diarization = config.diarization_config.unwrap();
+        assert!(diarization.enable_speaker_diarization);
+        assert_eq!(
+            (diarization.min_speaker_count, diarization.max_speaker_count),
+            (2, 4)
+        );
+        assert_eq!(config.model, "phone_call");
+        assert!(!config.use_enhanced);
+    }
+
+    fn prepared(audio: Audio, config: &mut RecognitionConfig) -> recognition_audio::AudioSource {
+        audio.prepare(config).unwrap().audio_source.unwrap()
+    }
+
+    #[test]
+    fn prepare_uri() {
+        let mut config = default_config();
+        let source = prepared(Audio::Uri("gs://bucket/audio.wav".to_owned()), &mut config);
+        assert_eq!(
+            source,
+            recognition_audio::AudioSource::Uri("gs://bucket/audio.wav".to_owned())
+        );
+        assert_eq!(config, default_config());
+    }
+
+    #[test]
+    fn prepare_inline_wav() {
+        let format = crate::audio::PcmFormat {
+            sample_rate: 16000,
+            channels: 2,
+            bits_per_sample: 16,
+        };
+        let wav = crate::audio::encode_wav(format, &[1, 2, 3, 4]);
+        let mut config = RecognitionConfigBuilder::new()
+            .encoding(AudioEncoding::EncodingUnspecified)
+            .build();
+
+        let source = prepared(Audio::from(wav), &mut config);
+        assert_eq!(
+            source,
+            recognition_audio::AudioSource::Content(vec![1, 2, 3, 4])
+        );
+        assert_eq!(config.encoding, AudioEncoding::Linear16 as i32);
+        assert_eq!(
+            (config.sample_rate_hertz, config.audio_channel_count),
+            (16000, 2)
+        );
+    }
+
+    #[test]
+    fn prepare_inline_raw_and_encoded() {
+        // raw samples keep the configured format
+        let mut config = default_config();
+        let source = prepared(Audio::Content(vec![0; 8]), &mut config);
+        assert_eq!(source, recognition_audio::AudioSource::Content(vec![0; 8]));
+        assert_eq!(config, default_config());
+
+        // other encodings are not inspected
+        let ogg = b"OggS....".to_vec();
+        let mut config = RecognitionConfigBuilder::new()
+            .encoding(AudioEncoding::OggOpus)
+            .build();
+        let source = prepared(Audio::Content(ogg.clone()), &mut config);
+        assert_eq!(source, recognition_audio::AudioSource::Content(ogg.clone()));
+
+        // unless LINEAR16 is expected
+        let result = Audio::Content(ogg).prepare(&mut default_config());
+        assert!(matches!(result, Err(crate::Error::UnsupportedAudio(_))));
+    }
 }
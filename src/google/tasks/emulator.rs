@@ -0,0 +1,770 @@
+//! In-process replacement of Cloud Tasks for tests.
+//!
+//! [`TasksClient`]s created by [`TasksEmulator::client`] store tasks in
+//! memory and dispatch them as plain HTTP requests when due, retrying
+//! failed ones. Queues are not emulated, tasks may be created in any queue.
+
+use super::{proto, to_std_duration, to_system_time, Attempt, QueueSettings, RetryConfig};
+use super::{HttpMethod, Task, TaskData, TaskHandle, TasksClient};
+use crate::Error;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tonic::transport::Channel;
+
+/// Routes task functions of [`google::tasks`](super) to `emulator`,
+/// in place of the service set up by the builder.
+pub async fn install(emulator: TasksEmulator) -> crate::Result<()> {
+    super::install(emulator.client().await?)
+}
+
+/// Emulated task service, clones share stored tasks.
+#[derive(Clone)]
+pub struct TasksEmulator {
+    tasks: Arc<Tasks>,
+    settings: Arc<Settings>,
+}
+
+#[derive(Default)]
+struct Tasks {
+    stored: Mutex<HashMap<String, StoredTask>>,
+    next_id: AtomicU64,
+}
+
+/// Settings of the emulator, tasks keep those they were created with.
+#[derive(Clone)]
+struct Settings {
+    client: reqwest::Client,
+    retry_config: RetryConfig,
+    /// Scheme and host tasks are dispatched to instead of their own.
+    target: Option<String>,
+}
+
+struct StoredTask {
+    task: Task,
+    request: proto::HttpRequest,
+    /// Status code of the last answered attempt.
+    previous_response: Option<u16>,
+    /// Wakes dispatcher waiting for schedule time.
+    wake: Arc<Notify>,
+}
+
+impl Default for TasksEmulator {
+    fn default() -> Self {
+        TasksEmulator::new()
+    }
+}
+
+impl TasksEmulator {
+    /// Emulator making 5 attempts with backoff from 100ms to 1s.
+    pub fn new() -> Self {
+        TasksEmulator {
+            tasks: Default::default(),
+            settings: Arc::new(Settings {
+                client: reqwest::Client::new(),
+                retry_config: RetryConfig {
+                    max_attempts: 5,
+                    max_retry_duration: None,
+                    min_backoff: Some(Duration::from_millis(100)),
+                    max_backoff: Some(Duration::from_secs(1)),
+                    max_doublings: 16,
+                },
+                target: None,
+            }),
+        }
+    }
+
+    /// Retries of failed tasks. Task is given up once it was attempted
+    /// `max_attempts` times and `max_retry_duration` passed since its first
+    /// attempt, `-1` and `None` lift the corresponding limit.
+    /// Clones made before keep their own settings.
+    pub fn retry_config(mut self, retry_config: RetryConfig) -> Self {
+        Arc::make_mut(&mut self.settings).retry_config = retry_config;
+        self
+    }
+
+    /// Sends tasks to `base_url`, e.g. `http://127.0.0.1:8080`,
+    /// keeping path and query of their urls.
+    /// Clones made before keep their own settings.
+    pub fn target(mut self, base_url: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.settings).target =
+            Some(base_url.into().trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Creates client whose requests are handled by the emulator.
+    pub async fn client(&self) -> crate::Result<TasksClient> {
+        // never connected to, requests do not reach the channel
+        let channel = Channel::from_static("http://cloudtasks.invalid").connect_lazy()?;
+        Ok(TasksClient {
+            channel,
+            auth: None,
+            emulator: Some(self.clone()),
+        })
+    }
+
+    pub async fn create_task(&self, task: TaskData<'_>) -> crate::Result<TaskHandle> {
+        let request = task.into_request();
+        let task = request.task.unwrap_or_default();
+        let name = if task.name.is_empty() {
+            let id = self.tasks.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+            format!("{}/tasks/{}", request.parent, id)
+        } else {
+            task.name
+        };
+        let request = match task.payload_type {
+            Some(proto::task::PayloadType::HttpRequest(request)) => request,
+            _ => return Err(Error::InvalidInput("task has no http request".to_owned())),
+        };
+
+        let now = SystemTime::now();
+        let stored = StoredTask {
+            task: Task {
+                handle: TaskHandle::from(name.clone()),
+                url: request.url.clone(),
+                schedule_time: Some(task.schedule_time.map_or(now, to_system_time)),
+                create_time: Some(now),
+                dispatch_deadline: task.dispatch_deadline.map(to_std_duration),
+                dispatch_count: 0,
+                response_count: 0,
+                first_attempt: None,
+                last_attempt: None,
+            },
+            request,
+            previous_response: None,
+            wake: Arc::new(Notify::new()),
+        };
+        let wake = stored.wake.clone();
+
+        {
+            let mut tasks = self.tasks.stored.lock().unwrap();
+            if tasks.contains_key(&name) {
+                return Err(tonic::Status::already_exists(format!("task {} exists", name)).into());
+            }
+            tasks.insert(name.clone(), stored);
+        }
+
+        tokio::spawn(dispatch(
+            self.tasks.clone(),
+            self.settings.clone(),
+            name.clone(),
+            wake,
+        ));
+        Ok(TaskHandle::from(name))
+    }
+
+    pub async fn create_tasks(
+        &self,
+        tasks: Vec<TaskData<'_>>,
+        concurrency: usize,
+    ) -> Vec<crate::Result<TaskHandle>> {
+        stream::iter(tasks)
+            .map(|task| self.create_task(task))
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    pub async fn create_json_task<T: serde::Serialize>(
+        &self,
+        task: TaskData<'_>,
+        payload: &T,
+    ) -> crate::Result<TaskHandle> {
+        self.create_task(task.with_json(payload)?).await
+    }
+
+    pub async fn get_task(&self, task: &TaskHandle) -> crate::Result<Task> {
+        self.tasks
+            .stored
+            .lock()
+            .unwrap()
+            .get(task.name())
+            .map(|stored| stored.task.clone())
+            .ok_or_else(|| not_found(task))
+    }
+
+    /// Lists tasks waiting in the queue, in order of their schedule time.
+    pub fn list_tasks(
+        &self,
+        queue: &QueueSettings<'_>,
+    ) -> impl Stream<Item = crate::Result<Task>> + Send + 'static {
+        let prefix = format!("{}/tasks/", queue.form_queue());
+        let mut tasks: Vec<Task> = self
+            .tasks
+            .stored
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|stored| stored.task.handle.name().starts_with(&prefix))
+            .map(|stored| stored.task.clone())
+            .collect();
+        tasks.sort_by_key(|task| task.schedule_time);
+        stream::iter(tasks.into_iter().map(Ok))
+    }
+
+    pub async fn delete_task(&self, task: &TaskHandle) -> crate::Result<()> {
+        let stored = self
+            .tasks
+            .stored
+            .lock()
+            .unwrap()
+            .remove(task.name())
+            .ok_or_else(|| not_found(task))?;
+        // let the dispatcher notice removal
+        stored.wake.notify_one();
+        Ok(())
+    }
+
+    pub async fn run_task(&self, task: &TaskHandle) -> crate::Result<Task> {
+        let mut tasks = self.tasks.stored.lock().unwrap();
+        let stored = tasks.get_mut(task.name()).ok_or_else(|| not_found(task))?;
+        stored.task.schedule_time = Some(SystemTime::now());
+        stored.wake.notify_one();
+        Ok(stored.task.clone())
+    }
+}
+
+/// Sends the task when it is due until it succeeds or runs out of attempts.
+async fn dispatch(tasks: Arc<Tasks>, settings: Arc<Settings>, name: String, wake: Arc<Notify>) {
+    loop {
+        // --------------------------------
+        // wait for schedule time
+        // --------------------------------
+        let schedule_time = match tasks.stored.lock().unwrap().get(&name) {
+            Some(stored) => stored.task.schedule_time.unwrap_or(UNIX_EPOCH),
+            None => return,
+        };
+        let delay = schedule_time
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = wake.notified() => {}
+        }
+
+        // --------------------------------
+        // construct request, unless task was deleted or rescheduled
+        // --------------------------------
+        let request = {
+            let mut stored_tasks = tasks.stored.lock().unwrap();
+            let stored = match stored_tasks.get_mut(&name) {
+                Some(stored) => stored,
+                None => return,
+            };
+            let now = SystemTime::now();
+            let schedule_time = stored.task.schedule_time.unwrap_or(UNIX_EPOCH);
+            if schedule_time > now {
+                continue;
+            }
+
+            let request = build_request(&settings, stored, schedule_time);
+            let attempt = Attempt {
+                schedule_time: Some(schedule_time),
+                dispatch_time: Some(now),
+                response_time: None,
+                response_status: None,
+            };
+            stored.task.dispatch_count += 1;
+            stored
+                .task
+                .first_attempt
+                .get_or_insert_with(|| attempt.clone());
+            stored.task.last_attempt = Some(attempt);
+            request
+        };
+
+        // --------------------------------
+        // send request
+        // --------------------------------
+        let status = match request {
+            Ok(request) => settings
+                .client
+                .execute(request)
+                .await
+                .ok()
+                .map(|r| r.status()),
+            Err(_) => None,
+        };
+
+        // --------------------------------
+        // record result and schedule retry
+        // --------------------------------
+        let mut stored_tasks = tasks.stored.lock().unwrap();
+        let stored = match stored_tasks.get_mut(&name) {
+            Some(stored) => stored,
+            None => return,
+        };
+        let now = SystemTime::now();
+        if let Some(status) = status {
+            stored.task.response_count += 1;
+            stored.previous_response = Some(status.as_u16());
+            if let Some(attempt) = &mut stored.task.last_attempt {
+                attempt.response_time = Some(now);
+                attempt.response_status = Some(to_code(status));
+            }
+        }
+
+        let attempts = stored.task.dispatch_count;
+        let first_attempt = stored
+            .task
+            .first_attempt
+            .as_ref()
+            .and_then(|attempt| attempt.dispatch_time)
+            .unwrap_or(now);
+        let retry = &settings.retry_config;
+        if matches!(status, Some(status) if status.is_success())
+            || retries_exhausted(
+                retry,
+                attempts,
+                now.duration_since(first_attempt).unwrap_or_default(),
+            )
+        {
+            stored_tasks.remove(&name);
+            return;
+        }
+        stored.task.schedule_time = Some(now + backoff(retry, attempts));
+    }
+}
+
+fn build_request(
+    settings: &Settings,
+    stored: &StoredTask,
+    schedule_time: SystemTime,
+) -> crate::Result<reqwest::Request> {
+    let request = &stored.request;
+    let url = reqwest::Url::parse(&request.url)
+        .map_err(|e| Error::InvalidInput(format!("task url is invalid: {}", e)))?;
+    let url = match &settings.target {
+        Some(target) => {
+            let query = url.query().map(|query| format!("?{}", query));
+            format!("{}{}{}", target, url.path(), query.unwrap_or_default())
+        }
+        None => url.to_string(),
+    };
+    let method = match HttpMethod::from_i32(request.http_method) {
+        Some(HttpMethod::Get) => reqwest::Method::GET,
+        Some(HttpMethod::Head) => reqwest::Method::HEAD,
+        Some(HttpMethod::Put) => reqwest::Method::PUT,
+        Some(HttpMethod::Delete) => reqwest::Method::DELETE,
+        Some(HttpMethod::Patch) => reqwest::Method::PATCH,
+        Some(HttpMethod::Options) => reqwest::Method::OPTIONS,
+        _ => reqwest::Method::POST,
+    };
+
+    // names are sent without the queue path, as the service does
+    let task = &stored.task;
+    let mut path = task.handle.name().rsplit('/');
+    let task_name = path.next().unwrap_or_default();
+    let queue_name = path.nth(1).unwrap_or_default();
+    let eta = schedule_time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+
+    let mut builder = settings
+        .client
+        .request(method, &url)
+        .header("X-CloudTasks-QueueName", queue_name)
+        .header("X-CloudTasks-TaskName", task_name)
+        .header("X-CloudTasks-TaskRetryCount", task.dispatch_count)
+        .header("X-CloudTasks-TaskExecutionCount", task.response_count)
+        .header("X-CloudTasks-TaskETA", format!("{:.6}", eta));
+    if let Some(status) = stored.previous_response {
+        builder = builder.header("X-CloudTasks-TaskPreviousResponse", status);
+    }
+    for (name, value) in &request.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    if let Some(deadline) = task.dispatch_deadline {
+        builder = builder.timeout(deadline);
+    }
+    Ok(builder.body(request.body.clone()).build()?)
+}
+
+/// Whether failed task is not retried anymore. As in the service, it takes
+/// both `max_attempts` attempts and `max_retry_duration` since the first
+/// attempt, when both are set, and retries go on forever when none is.
+fn retries_exhausted(retry: &RetryConfig, attempts: i32, since_first_attempt: Duration) -> bool {
+    let attempts_exhausted =
+        Some(attempts >= retry.max_attempts).filter(|_| retry.max_attempts >= 0);
+    let duration_exhausted = retry
+        .max_retry_duration
+        .filter(|max| *max > Duration::ZERO)
+        .map(|max| since_first_attempt > max);
+    match (attempts_exhausted, duration_exhausted) {
+        (None, None) => false,
+        (attempts, duration) => attempts.unwrap_or(true) && duration.unwrap_or(true),
+    }
+}
+
+/// Delay before the next attempt, doubling from `min_backoff`
+/// `max_doublings` times, capped by `max_backoff`.
+fn backoff(retry: &RetryConfig, attempts: i32) -> Duration {
+    let min_backoff = retry
+        .min_backoff
+        .unwrap_or_else(|| Duration::from_millis(100));
+    let max_backoff = retry
+        .max_backoff
+        .unwrap_or_else(|| Duration::from_secs(3600));
+    let doublings = (attempts - 1).clamp(0, retry.max_doublings.clamp(0, 30));
+    (min_backoff * 2u32.pow(doublings as u32)).min(max_backoff)
+}
+
+fn to_code(status: reqwest::StatusCode) -> tonic::Code {
+    match status.as_u16() {
+        200..=299 => tonic::Code::Ok,
+        400 => tonic::Code::InvalidArgument,
+        401 => tonic::Code::Unauthenticated,
+        403 => tonic::Code::PermissionDenied,
+        404 => tonic::Code::NotFound,
+        409 => tonic::Code::Aborted,
+        429 => tonic::Code::ResourceExhausted,
+        501 => tonic::Code::Unimplemented,
+        503 => tonic::Code::Unavailable,
+        504 => tonic::Code::DeadlineExceeded,
+        _ => tonic::Code::Unknown,
+    }
+}
+
+fn not_found(task: &TaskHandle) -> Error {
+    tonic::Status::not_found(format!("task {} does not exist", task.name())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    const QUEUE: QueueSettings<'static> = QueueSettings {
+        project_id: "project",
+        location: "location",
+        queue_name: "queue",
+    };
+
+    fn task(name: &str) -> TaskData<'static> {
+        TaskData::new(QUEUE, "http://127.0.0.1:9/task".to_owned(), vec![])
+            .name(format!("{}/tasks/{}", QUEUE.form_queue(), name))
+            .schedule_after(Duration::from_secs(3600))
+    }
+
+    /// Request received by [`serve`].
+    struct Received {
+        method: String,
+        path: String,
+        /// Lowercase names.
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    /// Serves HTTP on a local port from a thread, answering requests with
+    /// `statuses` in turn, the last one repeated. Returns base url
+    /// of the server and requests it received.
+    fn serve(statuses: Vec<u16>) -> (String, UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                let mut request_line = line.split_whitespace();
+                let method = request_line.next().unwrap_or_default().to_owned();
+                let path = request_line.next().unwrap_or_default().to_owned();
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => {
+                            headers.insert(name.to_lowercase(), value.to_owned());
+                        }
+                        None => break,
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).unwrap();
+
+                let received = Received {
+                    method,
+                    path,
+                    headers,
+                    body,
+                };
+                let stopped = sender.send(received).is_err();
+                let status = statuses[i.min(statuses.len() - 1)];
+                write!(
+                    stream.get_mut(),
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                if stopped {
+                    return;
+                }
+            }
+        });
+        (url, receiver)
+    }
+
+    async fn next(received: &mut UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("task was not delivered")
+            .unwrap()
+    }
+
+    /// Polls the task until `ready` holds for it, `None` stands for removed task.
+    async fn wait_task(
+        emulator: &TasksEmulator,
+        handle: &TaskHandle,
+        ready: impl Fn(Option<&Task>) -> bool,
+    ) -> Option<Task> {
+        let poll = async {
+            loop {
+                let task = emulator.get_task(handle).await.ok();
+                if ready(task.as_ref()) {
+                    return task;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), poll)
+            .await
+            .expect("task did not reach expected state")
+    }
+
+    #[tokio::test]
+    async fn clients_share_tasks_of_emulator() {
+        let emulator = TasksEmulator::new();
+        let first = emulator.client().await.unwrap();
+        let second = emulator.client().await.unwrap();
+        let other = TasksEmulator::new().client().await.unwrap();
+
+        let handle = first.create_task(task("a")).await.unwrap();
+        assert_eq!(second.get_task(&handle).await.unwrap().handle, handle);
+        assert!(other.get_task(&handle).await.is_err());
+
+        let results = second
+            .create_tasks(vec![task("b"), task("a"), task("c")], 2)
+            .await;
+        assert!(results[0].is_ok() && results[1].is_err() && results[2].is_ok());
+
+        let listed: Vec<Task> = first.list_tasks(&QUEUE).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 3);
+        first.delete_task(&handle).await.unwrap();
+        assert!(second.get_task(&handle).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn create_tasks_results_in_order_of_tasks() {
+        let client = TasksEmulator::new().client().await.unwrap();
+        client.create_task(task("b")).await.unwrap();
+
+        let names = ["a", "b", "c", "a", "d", "e"];
+        let tasks = names.iter().map(|name| task(name)).collect();
+        let results = client.create_tasks(tasks, 3).await;
+
+        assert_eq!(results.len(), names.len());
+        for (i, (result, name)) in results.iter().zip(names.iter()).enumerate() {
+            match result {
+                Ok(handle) => {
+                    assert!(handle.name().ends_with(&format!("/tasks/{}", name)));
+                }
+                Err(Error::Status(status)) => {
+                    assert_eq!(status.code(), tonic::Code::AlreadyExists);
+                }
+                Err(e) => panic!("unexpected error {}", e),
+            }
+            // existing task and the second one of the same name fail
+            assert_eq!(result.is_err(), i == 1 || i == 3, "task {}", i);
+        }
+        let listed: Vec<Task> = client.list_tasks(&QUEUE).try_collect().await.unwrap();
+        assert_eq!(listed.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn clones_configured_after_sharing() {
+        let emulator = TasksEmulator::new();
+        let client = emulator.client().await.unwrap();
+        let configured = emulator
+            .clone()
+            .target("http://127.0.0.1:9")
+            .retry_config(RetryConfig {
+                max_attempts: 1,
+                ..Default::default()
+            });
+        assert_eq!(configured.settings.retry_config.max_attempts, 1);
+        assert_eq!(emulator.settings.retry_config.max_attempts, 5);
+
+        // settings diverge, stored tasks stay shared
+        configured.create_task(task("a")).await.unwrap();
+        assert_eq!(client.list_tasks(&QUEUE).count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn task_delivered_with_method_headers_and_body() {
+        let (url, mut received) = serve(vec![200]);
+        let emulator = TasksEmulator::new().target(url);
+        let task = TaskData::new(
+            QUEUE,
+            "https://tasks.invalid/task?id=1".to_owned(),
+            b"payload".to_vec(),
+        )
+        .name(format!("{}/tasks/a", QUEUE.form_queue()))
+        .http_method(HttpMethod::Put)
+        .header("X-Custom", "value");
+        let handle = emulator.create_task(task).await.unwrap();
+
+        let request = next(&mut received).await;
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/task?id=1");
+        assert_eq!(request.body, b"payload");
+        assert_eq!(request.headers["x-custom"], "value");
+        assert_eq!(request.headers["x-cloudtasks-queuename"], "queue");
+        assert_eq!(request.headers["x-cloudtasks-taskname"], "a");
+        assert_eq!(request.headers["x-cloudtasks-taskretrycount"], "0");
+        assert_eq!(request.headers["x-cloudtasks-taskexecutioncount"], "0");
+        assert!(!request
+            .headers
+            .contains_key("x-cloudtasks-taskpreviousresponse"));
+
+        wait_task(&emulator, &handle, |task| task.is_none()).await;
+    }
+
+    #[tokio::test]
+    async fn task_not_delivered_before_schedule_time() {
+        let (url, mut received) = serve(vec![200]);
+        let emulator = TasksEmulator::new().target(url);
+        let schedule_time = SystemTime::now() + Duration::from_millis(300);
+        emulator
+            .create_task(task("a").schedule_at(schedule_time))
+            .await
+            .unwrap();
+
+        let early = tokio::time::timeout(Duration::from_millis(200), received.recv()).await;
+        assert!(early.is_err());
+        let request = next(&mut received).await;
+        assert!(SystemTime::now() >= schedule_time);
+
+        let eta: f64 = request.headers["x-cloudtasks-tasketa"].parse().unwrap();
+        let expected = schedule_time.duration_since(UNIX_EPOCH).unwrap();
+        assert!((eta - expected.as_secs_f64()).abs() < 1e-3);
+    }
+
+    #[tokio::test]
+    async fn server_errors_retried_until_success() {
+        let (url, mut received) = serve(vec![500, 503, 200]);
+        let emulator = TasksEmulator::new().target(url).retry_config(RetryConfig {
+            max_attempts: 5,
+            max_retry_duration: None,
+            min_backoff: Some(Duration::from_millis(200)),
+            max_backoff: Some(Duration::from_millis(200)),
+            max_doublings: 0,
+        });
+        let handle = emulator
+            .create_task(task("a").schedule_after(Duration::ZERO))
+            .await
+            .unwrap();
+
+        let first = next(&mut received).await;
+        let task = wait_task(&emulator, &handle, |task| {
+            task.is_some_and(|task| task.response_count == 1)
+        })
+        .await
+        .unwrap();
+        assert_eq!(task.dispatch_count, 1);
+        let attempt = task.last_attempt.unwrap();
+        assert_eq!(attempt.response_status, Some(tonic::Code::Unknown));
+        assert_eq!(
+            task.first_attempt.unwrap().dispatch_time,
+            attempt.dispatch_time
+        );
+        assert!(task.schedule_time > attempt.response_time);
+
+        let second = next(&mut received).await;
+        let third = next(&mut received).await;
+        let requests = [first, second, third];
+        let counters: Vec<_> = requests
+            .iter()
+            .map(|request| {
+                let header = |name: &str| request.headers.get(name).map(String::as_str);
+                (
+                    header("x-cloudtasks-taskretrycount"),
+                    header("x-cloudtasks-taskexecutioncount"),
+                    header("x-cloudtasks-taskpreviousresponse"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            counters,
+            vec![
+                (Some("0"), Some("0"), None),
+                (Some("1"), Some("1"), Some("500")),
+                (Some("2"), Some("2"), Some("503")),
+            ]
+        );
+
+        // removed once succeeded
+        wait_task(&emulator, &handle, |task| task.is_none()).await;
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_retry_duration() {
+        let (url, mut received) = serve(vec![500]);
+        let emulator = TasksEmulator::new().target(url).retry_config(RetryConfig {
+            max_attempts: -1,
+            max_retry_duration: Some(Duration::from_millis(250)),
+            min_backoff: Some(Duration::from_millis(100)),
+            max_backoff: Some(Duration::from_millis(100)),
+            max_doublings: 0,
+        });
+        let handle = emulator
+            .create_task(task("a").schedule_after(Duration::ZERO))
+            .await
+            .unwrap();
+
+        next(&mut received).await;
+        let first = std::time::Instant::now();
+        wait_task(&emulator, &handle, |task| task.is_none()).await;
+        let given_up = first.elapsed();
+        let mut attempts = 1;
+        while received.try_recv().is_ok() {
+            attempts += 1;
+        }
+        assert!(attempts >= 3, "{} attempts", attempts);
+        assert!(given_up >= Duration::from_millis(250), "{:?}", given_up);
+    }
+
+    #[test]
+    fn retries_exhausted_by_both_limits() {
+        let retry = |max_attempts, max_retry_duration| RetryConfig {
+            max_attempts,
+            max_retry_duration,
+            ..Default::default()
+        };
+        let second = Some(Duration::from_secs(1));
+        let (short, long) = (Duration::from_millis(500), Duration::from_secs(2));
+
+        assert!(!retries_exhausted(&retry(-1, None), 100, long));
+        assert!(!retries_exhausted(&retry(3, None), 2, long));
+        assert!(retries_exhausted(&retry(3, None), 3, short));
+        assert!(!retries_exhausted(&retry(-1, second), 100, short));
+        assert!(retries_exhausted(&retry(-1, second), 1, long));
+        assert!(!retries_exhausted(&retry(3, second), 3, short));
+        assert!(!retries_exhausted(&retry(3, second), 2, long));
+        assert!(retries_exhausted(&retry(3, second), 3, long));
+        // zero duration is no limit
+        assert!(retries_exhausted(&retry(3, Some(Duration::ZERO)), 3, short));
+    }
+}
@@ -0,0 +1,619 @@
+crate::rpc_service!(
+    TasksClient,
+    "cloudtasks",
+    "https://www.googleapis.com/auth/cloud-platform";
+    #[cfg(feature = "google-tasks-emulator")]
+    emulator: Option<emulator::TasksEmulator>
+);
+#[cfg(feature = "google-tasks-emulator")]
+pub mod emulator;
+mod queue;
+pub use queue::*;
+#[cfg(feature = "google-tasks-hyper-receiver")]
+mod receiver;
+#[cfg(feature = "google-tasks-hyper-receiver")]
+pub use receiver::*;
+
+use super::generated::google::cloud::tasks::v2beta3 as proto;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use proto::cloud_tasks_client;
+pub use proto::http_request::AuthorizationHeader;
+pub use proto::{HttpMethod, OAuthToken, OidcToken};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct QueueSettings<'a> {
+    pub project_id: &'a str,
+    pub location: &'a str,
+    pub queue_name: &'a str,
+}
+
+impl<'a> QueueSettings<'a> {
+    fn form_location(&self) -> String {
+        format!("projects/{}/locations/{}", self.project_id, self.location)
+    }
+
+    fn form_queue(&self) -> String {
+        format!(
+            "projects/{}/locations/{}/queues/{}",
+            self.project_id, self.location, self.queue_name
+        )
+    }
+}
+
+pub struct TaskData<'a> {
+    pub url: String,
+    pub body: Vec<u8>,
+    pub queue: QueueSettings<'a>,
+    /// Task id unique within the queue. Tasks with the id of an existing
+    /// or recently deleted task are rejected, which deduplicates them.
+    pub name: Option<String>,
+    /// Time of dispatch, immediately when `None`.
+    pub schedule: Option<Schedule>,
+    /// How long the handler may take, up to 30 minutes.
+    pub dispatch_deadline: Option<Duration>,
+    pub http_method: HttpMethod,
+    pub headers: HashMap<String, String>,
+    /// Token Cloud Tasks generates for the target,
+    /// the `Authorization` header is overridden by it.
+    pub authorization_header: Option<AuthorizationHeader>,
+}
+
+/// When the task is to be dispatched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    At(SystemTime),
+    /// Delay counted from task creation.
+    After(Duration),
+}
+
+impl<'a> TaskData<'a> {
+    /// Task posting `body` to `url` with `Content-Type: application/json`
+    /// header, which can be replaced with [`header`](Self::header).
+    pub fn new(queue: QueueSettings<'a>, url: String, body: Vec<u8>) -> Self {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_owned(), "application/json".to_owned());
+        TaskData {
+            url,
+            body,
+            queue,
+            name: None,
+            schedule: None,
+            dispatch_deadline: None,
+            http_method: HttpMethod::Post,
+            headers,
+            authorization_header: None,
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn schedule_at(mut self, time: SystemTime) -> Self {
+        self.schedule = Some(Schedule::At(time));
+        self
+    }
+
+    pub fn schedule_after(mut self, delay: Duration) -> Self {
+        self.schedule = Some(Schedule::After(delay));
+        self
+    }
+
+    pub fn dispatch_deadline(mut self, deadline: Duration) -> Self {
+        self.dispatch_deadline = Some(deadline);
+        self
+    }
+
+    pub fn http_method(mut self, http_method: HttpMethod) -> Self {
+        self.http_method = http_method;
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Attaches OIDC token, as required by Cloud Run and Cloud Functions.
+    /// Empty `audience` means the task url.
+    pub fn oidc_token(
+        mut self,
+        service_account_email: impl Into<String>,
+        audience: impl Into<String>,
+    ) -> Self {
+        self.authorization_header = Some(AuthorizationHeader::OidcToken(OidcToken {
+            service_account_email: service_account_email.into(),
+            audience: audience.into(),
+        }));
+        self
+    }
+
+    /// Attaches OAuth access token, as required by Google APIs.
+    /// Empty `scope` means `https://www.googleapis.com/auth/cloud-platform`.
+    pub fn oauth_token(
+        mut self,
+        service_account_email: impl Into<String>,
+        scope: impl Into<String>,
+    ) -> Self {
+        self.authorization_header = Some(AuthorizationHeader::OauthToken(OAuthToken {
+            service_account_email: service_account_email.into(),
+            scope: scope.into(),
+        }));
+        self
+    }
+
+    fn with_json<T: serde::Serialize>(mut self, payload: &T) -> crate::Result<Self> {
+        self.body = serde_json::to_vec(payload)?;
+        self.headers
+            .insert("Content-Type".to_owned(), "application/json".to_owned());
+        Ok(self)
+    }
+
+    fn into_request(self) -> proto::CreateTaskRequest {
+        let queue = self.queue.form_queue();
+
+        let schedule_time = self.schedule.map(|schedule| match schedule {
+            Schedule::At(time) => time,
+            Schedule::After(delay) => SystemTime::now() + delay,
+        });
+
+        proto::CreateTaskRequest {
+            parent: queue.clone(),
+            task: Some(proto::Task {
+                // full name is required, ids are accepted for convenience
+                name: self
+                    .name
+                    .map(|name| {
+                        if name.contains('/') {
+                            name
+                        } else {
+                            format!("{}/tasks/{}", queue, name)
+                        }
+                    })
+                    .unwrap_or_default(),
+                schedule_time: schedule_time.map(to_proto_timestamp),
+                dispatch_deadline: self.dispatch_deadline.map(to_proto_duration),
+                payload_type: Some(proto::task::PayloadType::HttpRequest(proto::HttpRequest {
+                    url: self.url,
+                    http_method: self.http_method as i32,
+                    body: self.body,
+                    headers: self.headers,
+                    authorization_header: self.authorization_header,
+                })),
+                ..proto::Task::default()
+            }),
+            ..proto::CreateTaskRequest::default()
+        }
+    }
+}
+
+/// Identifies created task by its full name,
+/// `projects/../locations/../queues/../tasks/..`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TaskHandle {
+    name: String,
+}
+
+impl TaskHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl From<String> for TaskHandle {
+    fn from(name: String) -> Self {
+        TaskHandle { name }
+    }
+}
+
+/// Task as reported by the service, payload body is omitted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub handle: TaskHandle,
+    /// Url of HTTP target.
+    pub url: String,
+    pub schedule_time: Option<SystemTime>,
+    pub create_time: Option<SystemTime>,
+    pub dispatch_deadline: Option<Duration>,
+    pub dispatch_count: i32,
+    pub response_count: i32,
+    pub first_attempt: Option<Attempt>,
+    pub last_attempt: Option<Attempt>,
+}
+
+/// Single dispatch of a task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    pub schedule_time: Option<SystemTime>,
+    pub dispatch_time: Option<SystemTime>,
+    pub response_time: Option<SystemTime>,
+    /// Result of the attempt, `None` while it is not finished.
+    pub response_status: Option<tonic::Code>,
+}
+
+impl From<proto::Task> for Task {
+    fn from(task: proto::Task) -> Self {
+        let url = match task.payload_type {
+            Some(proto::task::PayloadType::HttpRequest(request)) => request.url,
+            _ => String::new(),
+        };
+        Task {
+            handle: TaskHandle::from(task.name),
+            url,
+            schedule_time: task.schedule_time.map(to_system_time),
+            create_time: task.create_time.map(to_system_time),
+            dispatch_deadline: task.dispatch_deadline.map(to_std_duration),
+            dispatch_count: task.dispatch_count,
+            response_count: task.response_count,
+            first_attempt: task.first_attempt.map(Attempt::from),
+            last_attempt: task.last_attempt.map(Attempt::from),
+        }
+    }
+}
+
+impl From<proto::Attempt> for Attempt {
+    fn from(attempt: proto::Attempt) -> Self {
+        Attempt {
+            schedule_time: attempt.schedule_time.map(to_system_time),
+            dispatch_time: attempt.dispatch_time.map(to_system_time),
+            response_time: attempt.response_time.map(to_system_time),
+            response_status: attempt
+                .response_status
+                .map(|status| tonic::Code::from_i32(status.code)),
+        }
+    }
+}
+
+pub async fn create_task(task: TaskData<'_>) -> crate::Result<TaskHandle> {
+    service()?.create_task(task).await
+}
+
+/// Creates task with `payload` serialized to JSON as its body.
+pub async fn create_json_task<T: serde::Serialize>(
+    task: TaskData<'_>,
+    payload: &T,
+) -> crate::Result<TaskHandle> {
+    create_task(task.with_json(payload)?).await
+}
+
+/// Creates tasks with at most `concurrency` requests in flight.
+///
+/// Results are in order of `tasks`, so failed ones can be retried.
+pub async fn create_tasks(
+    tasks: Vec<TaskData<'_>>,
+    concurrency: usize,
+) -> Vec<crate::Result<TaskHandle>> {
+    match service() {
+        Ok(service) => service.create_tasks(tasks, concurrency).await,
+        Err(_) => tasks
+            .iter()
+            .map(|_| Err(crate::Error::NotInitialized("cloudtasks")))
+            .collect(),
+    }
+}
+
+pub async fn get_task(task: &TaskHandle) -> crate::Result<Task> {
+    service()?.get_task(task).await
+}
+
+/// Lists tasks of the queue, pages are requested as the stream is polled.
+pub fn list_tasks(queue: &QueueSettings<'_>) -> impl Stream<Item = crate::Result<Task>> {
+    match service() {
+        Ok(service) => service.list_tasks(queue).boxed(),
+        Err(e) => stream::once(async { Err(e) }).boxed(),
+    }
+}
+
+/// Deletes task, so it is not dispatched anymore.
+pub async fn delete_task(task: &TaskHandle) -> crate::Result<()> {
+    service()?.delete_task(task).await
+}
+
+/// Dispatches task immediately, regardless of its schedule time
+/// and queue rate limits.
+pub async fn run_task(task: &TaskHandle) -> crate::Result<Task> {
+    service()?.run_task(task).await
+}
+
+impl TasksClient {
+    pub async fn create_task(&self, task: TaskData<'_>) -> crate::Result<TaskHandle> {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.create_task(task).await;
+        }
+        let request = task.into_request();
+
+        let mut service = self
+            .grpc_client(&format!("parent={}", request.parent))
+            .await?;
+
+        let response = service.create_task(request).await?;
+
+        Ok(TaskHandle::from(response.into_inner().name))
+    }
+
+    /// Creates tasks sharing one token, see [`create_tasks`].
+    pub async fn create_tasks(
+        &self,
+        tasks: Vec<TaskData<'_>>,
+        concurrency: usize,
+    ) -> Vec<crate::Result<TaskHandle>> {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.create_tasks(tasks, concurrency).await;
+        }
+        let requests: Vec<_> = tasks.into_iter().map(TaskData::into_request).collect();
+
+        // --------------------------------
+        // retrieve token and construct channel for every queue
+        // --------------------------------
+        let token = match self.authorization().await {
+            Ok(token) => token,
+            Err(e) => {
+                let e = Arc::new(e);
+                return requests
+                    .iter()
+                    .map(|_| Err(crate::Error::Shared(e.clone())))
+                    .collect();
+            }
+        };
+        let mut services = HashMap::new();
+        for request in &requests {
+            if !services.contains_key(&request.parent) {
+                let service = self
+                    .grpc_client_with_token(token.clone(), &format!("parent={}", request.parent));
+                services.insert(request.parent.clone(), service);
+            }
+        }
+
+        // --------------------------------
+        // send requests
+        // --------------------------------
+        stream::iter(requests)
+            .map(|request| {
+                let service = match &services[&request.parent] {
+                    Ok(service) => Ok(service.clone()),
+                    Err(_) => Err(crate::Error::InvalidInput(format!(
+                        "queue name {} is not a valid header value",
+                        request.parent
+                    ))),
+                };
+                async move {
+                    let response = service?.create_task(request).await?;
+                    Ok(TaskHandle::from(response.into_inner().name))
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    pub async fn create_json_task<T: serde::Serialize>(
+        &self,
+        task: TaskData<'_>,
+        payload: &T,
+    ) -> crate::Result<TaskHandle> {
+        self.create_task(task.with_json(payload)?).await
+    }
+
+    pub async fn get_task(&self, task: &TaskHandle) -> crate::Result<Task> {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.get_task(task).await;
+        }
+        let mut service = self.grpc_client(&format!("name={}", task.name)).await?;
+        let response = service
+            .get_task(proto::GetTaskRequest {
+                name: task.name.clone(),
+                ..proto::GetTaskRequest::default()
+            })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub fn list_tasks(
+        &self,
+        queue: &QueueSettings<'_>,
+    ) -> impl Stream<Item = crate::Result<Task>> + '_ {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.list_tasks(queue).boxed();
+        }
+        let parent = queue.form_queue();
+
+        list_pages(move |page_token| {
+            let parent = parent.clone();
+            async move {
+                let mut service = self.grpc_client(&format!("parent={}", parent)).await?;
+                let response = service
+                    .list_tasks(proto::ListTasksRequest {
+                        parent,
+                        page_token,
+                        ..proto::ListTasksRequest::default()
+                    })
+                    .await?;
+                Ok(response.into_inner())
+            }
+        })
+        .map_ok(Task::from)
+        .boxed()
+    }
+
+    pub async fn delete_task(&self, task: &TaskHandle) -> crate::Result<()> {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.delete_task(task).await;
+        }
+        let mut service = self.grpc_client(&format!("name={}", task.name)).await?;
+        service
+            .delete_task(proto::DeleteTaskRequest {
+                name: task.name.clone(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn run_task(&self, task: &TaskHandle) -> crate::Result<Task> {
+        #[cfg(feature = "google-tasks-emulator")]
+        if let Some(emulator) = &self.emulator {
+            return emulator.run_task(task).await;
+        }
+        let mut service = self.grpc_client(&format!("name={}", task.name)).await?;
+        let response = service
+            .run_task(proto::RunTaskRequest {
+                name: task.name.clone(),
+                ..proto::RunTaskRequest::default()
+            })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// Creates grpc client authorizing requests, `request_params`
+    /// route them to the resource they are about.
+    async fn grpc_client(
+        &self,
+        request_params: &str,
+    ) -> crate::Result<cloud_tasks_client::CloudTasksClient<Channel>> {
+        let token = self.authorization().await?;
+        self.grpc_client_with_token(token, request_params)
+    }
+
+    fn grpc_client_with_token(
+        &self,
+        token: Option<MetadataValue<Ascii>>,
+        request_params: &str,
+    ) -> crate::Result<cloud_tasks_client::CloudTasksClient<Channel>> {
+        let request_params = MetadataValue::from_str(request_params)?;
+
+        Ok(cloud_tasks_client::CloudTasksClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![("x-goog-request-params", request_params)]),
+        ))
+    }
+}
+
+/// Tasks of pages returned by `page` for the token of the next page,
+/// starting with the empty one, until a page has no next page token.
+fn list_pages<'a, F, R>(mut page: F) -> impl Stream<Item = crate::Result<proto::Task>> + 'a
+where
+    F: FnMut(String) -> R + 'a,
+    R: Future<Output = crate::Result<proto::ListTasksResponse>> + 'a,
+{
+    // state is the token of the next page, `None` after the last one
+    stream::try_unfold(Some(String::new()), move |page_token| {
+        let response = page_token.map(&mut page);
+        async move {
+            let response = match response {
+                Some(response) => response.await?,
+                None => return Ok(None),
+            };
+            let tasks = response.tasks.into_iter().map(crate::Result::Ok);
+            let next_page_token = Some(response.next_page_token).filter(|token| !token.is_empty());
+            crate::Result::Ok(Some((stream::iter(tasks), next_page_token)))
+        }
+    })
+    .try_flatten()
+}
+
+fn to_std_duration(val: prost_types::Duration) -> Duration {
+    Duration::new(val.seconds.max(0) as u64, val.nanos.max(0) as u32)
+}
+
+fn to_proto_duration(val: Duration) -> prost_types::Duration {
+    prost_types::Duration {
+        seconds: val.as_secs() as i64,
+        nanos: val.subsec_nanos() as i32,
+    }
+}
+
+fn to_proto_timestamp(val: SystemTime) -> prost_types::Timestamp {
+    let since_epoch = val.duration_since(UNIX_EPOCH).unwrap_or_default();
+    prost_types::Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+fn to_system_time(val: prost_types::Timestamp) -> SystemTime {
+    UNIX_EPOCH + Duration::new(val.seconds.max(0) as u64, val.nanos.max(0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn page(names: &[&str], next_page_token: &str) -> crate::Result<proto::ListTasksResponse> {
+        Ok(proto::ListTasksResponse {
+            tasks: names
+                .iter()
+                .map(|name| proto::Task {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            next_page_token: next_page_token.to_owned(),
+        })
+    }
+
+    /// Lists `pages` returned in turn, along with tokens they were requested with.
+    fn list(pages: Vec<crate::Result<proto::ListTasksResponse>>) -> (Vec<String>, Vec<String>) {
+        let tokens = RefCell::new(vec![]);
+        let mut pages = pages.into_iter();
+        let tasks = list_pages(|page_token| {
+            tokens.borrow_mut().push(page_token);
+            let page = pages.next().expect("page after the last one");
+            async move { page }
+        });
+        let results: Vec<_> = futures::executor::block_on(tasks.collect());
+        let names = results
+            .into_iter()
+            .map(|result| match result {
+                Ok(task) => task.name,
+                Err(e) => format!("error: {}", e),
+            })
+            .collect();
+        (names, tokens.into_inner())
+    }
+
+    #[test]
+    fn pages_requested_with_next_page_token() {
+        let (names, tokens) = list(vec![
+            page(&["a", "b"], "first"),
+            // empty page does not end the listing while there is a token
+            page(&[], "second"),
+            page(&["c"], ""),
+        ]);
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(tokens, vec!["", "first", "second"]);
+    }
+
+    #[test]
+    fn single_page_without_token() {
+        let (names, tokens) = list(vec![page(&["a"], "")]);
+        assert_eq!(names, vec!["a"]);
+        assert_eq!(tokens, vec![""]);
+
+        let (names, tokens) = list(vec![page(&[], "")]);
+        assert!(names.is_empty());
+        assert_eq!(tokens.len(), 1);
+    }
+
+    #[test]
+    fn listing_stops_on_error() {
+        let unavailable = || Err(tonic::Status::unavailable("unavailable").into());
+        let (names, tokens) = list(vec![page(&["a"], "first"), unavailable()]);
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0], "a");
+        assert!(names[1].starts_with("error"));
+        assert_eq!(tokens, vec!["", "first"]);
+
+        let (names, tokens) = list(vec![unavailable()]);
+        assert_eq!(names.len(), 1);
+        assert!(names[0].starts_with("error"));
+        assert_eq!(tokens.len(), 1);
+    }
+}
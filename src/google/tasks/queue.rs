@@ -0,0 +1,389 @@
+use super::{
+    service, to_proto_duration, to_std_duration, to_system_time, QueueSettings, TasksClient,
+};
+use crate::google::generated::google::cloud::tasks::v2beta3 as proto;
+use std::time::{Duration, SystemTime};
+
+/// Queue as reported by the service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Queue {
+    /// Full name, `projects/../locations/../queues/..`.
+    pub name: String,
+    pub rate_limits: RateLimits,
+    pub retry_config: RetryConfig,
+    pub state: QueueState,
+    /// Last time the queue was purged.
+    pub purge_time: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueState {
+    Unspecified,
+    Running,
+    Paused,
+    Disabled,
+}
+
+/// How fast tasks of the queue are dispatched.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    pub max_dispatches_per_second: f64,
+    /// Chosen by the service, ignored on create and update.
+    pub max_burst_size: i32,
+    pub max_concurrent_dispatches: i32,
+}
+
+/// How failed tasks of the queue are retried.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryConfig {
+    /// `-1` for unlimited attempts.
+    pub max_attempts: i32,
+    pub max_retry_duration: Option<Duration>,
+    pub min_backoff: Option<Duration>,
+    pub max_backoff: Option<Duration>,
+    pub max_doublings: i32,
+}
+
+/// Settings applied on queue create or update,
+/// `None` keeps the current or default value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueueConfig {
+    pub rate_limits: Option<RateLimits>,
+    pub retry_config: Option<RetryConfig>,
+}
+
+impl From<proto::Queue> for Queue {
+    fn from(queue: proto::Queue) -> Self {
+        let state = match proto::queue::State::from_i32(queue.state) {
+            Some(proto::queue::State::Running) => QueueState::Running,
+            Some(proto::queue::State::Paused) => QueueState::Paused,
+            Some(proto::queue::State::Disabled) => QueueState::Disabled,
+            _ => QueueState::Unspecified,
+        };
+        Queue {
+            name: queue.name,
+            rate_limits: queue.rate_limits.map(RateLimits::from).unwrap_or_default(),
+            retry_config: queue
+                .retry_config
+                .map(RetryConfig::from)
+                .unwrap_or_default(),
+            state,
+            purge_time: queue.purge_time.map(to_system_time),
+        }
+    }
+}
+
+impl From<proto::RateLimits> for RateLimits {
+    fn from(limits: proto::RateLimits) -> Self {
+        RateLimits {
+            max_dispatches_per_second: limits.max_dispatches_per_second,
+            max_burst_size: limits.max_burst_size,
+            max_concurrent_dispatches: limits.max_concurrent_dispatches,
+        }
+    }
+}
+
+impl From<RateLimits> for proto::RateLimits {
+    fn from(limits: RateLimits) -> Self {
+        proto::RateLimits {
+            max_dispatches_per_second: limits.max_dispatches_per_second,
+            max_burst_size: limits.max_burst_size,
+            max_concurrent_dispatches: limits.max_concurrent_dispatches,
+        }
+    }
+}
+
+impl From<proto::RetryConfig> for RetryConfig {
+    fn from(config: proto::RetryConfig) -> Self {
+        RetryConfig {
+            max_attempts: config.max_attempts,
+            max_retry_duration: config.max_retry_duration.map(to_std_duration),
+            min_backoff: config.min_backoff.map(to_std_duration),
+            max_backoff: config.max_backoff.map(to_std_duration),
+            max_doublings: config.max_doublings,
+        }
+    }
+}
+
+impl From<RetryConfig> for proto::RetryConfig {
+    fn from(config: RetryConfig) -> Self {
+        proto::RetryConfig {
+            max_attempts: config.max_attempts,
+            max_retry_duration: config.max_retry_duration.map(to_proto_duration),
+            min_backoff: config.min_backoff.map(to_proto_duration),
+            max_backoff: config.max_backoff.map(to_proto_duration),
+            max_doublings: config.max_doublings,
+        }
+    }
+}
+
+impl QueueConfig {
+    fn into_queue(self, name: String) -> proto::Queue {
+        proto::Queue {
+            name,
+            rate_limits: self.rate_limits.map(Into::into),
+            retry_config: self.retry_config.map(Into::into),
+            ..proto::Queue::default()
+        }
+    }
+
+    /// Fields of the queue the config sets.
+    fn update_mask(&self) -> prost_types::FieldMask {
+        let mut paths = vec![];
+        if self.rate_limits.is_some() {
+            paths.push("rate_limits.max_dispatches_per_second".to_owned());
+            paths.push("rate_limits.max_concurrent_dispatches".to_owned());
+        }
+        if self.retry_config.is_some() {
+            paths.push("retry_config".to_owned());
+        }
+        prost_types::FieldMask { paths }
+    }
+}
+
+/// Lists all queues of the location.
+pub async fn list_queues(project_id: &str, location: &str) -> crate::Result<Vec<Queue>> {
+    service()?.list_queues(project_id, location).await
+}
+
+pub async fn get_queue(queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+    service()?.get_queue(queue).await
+}
+
+pub async fn create_queue(queue: &QueueSettings<'_>, config: QueueConfig) -> crate::Result<Queue> {
+    service()?.create_queue(queue, config).await
+}
+
+/// Changes settings present in `config`, others are left as is.
+pub async fn update_queue(queue: &QueueSettings<'_>, config: QueueConfig) -> crate::Result<Queue> {
+    service()?.update_queue(queue, config).await
+}
+
+/// Deletes queue along with its tasks.
+pub async fn delete_queue(queue: &QueueSettings<'_>) -> crate::Result<()> {
+    service()?.delete_queue(queue).await
+}
+
+/// Deletes all tasks of the queue.
+pub async fn purge_queue(queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+    service()?.purge_queue(queue).await
+}
+
+/// Stops dispatching tasks, new tasks can still be added.
+pub async fn pause_queue(queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+    service()?.pause_queue(queue).await
+}
+
+pub async fn resume_queue(queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+    service()?.resume_queue(queue).await
+}
+
+impl TasksClient {
+    pub async fn list_queues(&self, project_id: &str, location: &str) -> crate::Result<Vec<Queue>> {
+        let parent = format!("projects/{}/locations/{}", project_id, location);
+        let mut service = self.grpc_client(&format!("parent={}", parent)).await?;
+
+        let mut queues = vec![];
+        let mut page_token = String::new();
+        loop {
+            let response = service
+                .list_queues(proto::ListQueuesRequest {
+                    parent: parent.clone(),
+                    page_token,
+                    ..proto::ListQueuesRequest::default()
+                })
+                .await?
+                .into_inner();
+            queues.extend(response.queues.into_iter().map(Queue::from));
+
+            if response.next_page_token.is_empty() {
+                return Ok(queues);
+            }
+            page_token = response.next_page_token;
+        }
+    }
+
+    pub async fn get_queue(&self, queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("name={}", name)).await?;
+        let response = service.get_queue(proto::GetQueueRequest { name }).await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn create_queue(
+        &self,
+        queue: &QueueSettings<'_>,
+        config: QueueConfig,
+    ) -> crate::Result<Queue> {
+        let parent = queue.form_location();
+        let mut service = self.grpc_client(&format!("parent={}", parent)).await?;
+        let response = service
+            .create_queue(proto::CreateQueueRequest {
+                parent,
+                queue: Some(config.into_queue(queue.form_queue())),
+            })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn update_queue(
+        &self,
+        queue: &QueueSettings<'_>,
+        config: QueueConfig,
+    ) -> crate::Result<Queue> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("queue.name={}", name)).await?;
+        let update_mask = config.update_mask();
+        let response = service
+            .update_queue(proto::UpdateQueueRequest {
+                queue: Some(config.into_queue(name)),
+                update_mask: Some(update_mask),
+            })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn delete_queue(&self, queue: &QueueSettings<'_>) -> crate::Result<()> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("name={}", name)).await?;
+        service
+            .delete_queue(proto::DeleteQueueRequest { name })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn purge_queue(&self, queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("name={}", name)).await?;
+        let response = service
+            .purge_queue(proto::PurgeQueueRequest { name })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn pause_queue(&self, queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("name={}", name)).await?;
+        let response = service
+            .pause_queue(proto::PauseQueueRequest { name })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+
+    pub async fn resume_queue(&self, queue: &QueueSettings<'_>) -> crate::Result<Queue> {
+        let name = queue.form_queue();
+        let mut service = self.grpc_client(&format!("name={}", name)).await?;
+        let response = service
+            .resume_queue(proto::ResumeQueueRequest { name })
+            .await?;
+        Ok(response.into_inner().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_config() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 10,
+            max_retry_duration: Some(Duration::from_secs(3600)),
+            min_backoff: Some(Duration::from_millis(1500)),
+            max_backoff: None,
+            max_doublings: 4,
+        }
+    }
+
+    #[test]
+    fn update_mask_of_set_fields() {
+        let paths = |config: QueueConfig| config.update_mask().paths;
+        assert!(paths(QueueConfig::default()).is_empty());
+
+        let rate_limits = QueueConfig {
+            rate_limits: Some(RateLimits::default()),
+            retry_config: None,
+        };
+        // burst size is chosen by the service and can not be updated
+        assert_eq!(
+            paths(rate_limits),
+            vec![
+                "rate_limits.max_dispatches_per_second",
+                "rate_limits.max_concurrent_dispatches"
+            ]
+        );
+
+        let both = QueueConfig {
+            rate_limits: Some(RateLimits::default()),
+            retry_config: Some(RetryConfig::default()),
+        };
+        assert_eq!(paths(both).last().map(String::as_str), Some("retry_config"));
+    }
+
+    #[test]
+    fn queue_of_config() {
+        let config = QueueConfig {
+            rate_limits: None,
+            retry_config: Some(retry_config()),
+        };
+        let queue = config.into_queue("projects/p/locations/l/queues/q".to_owned());
+        assert_eq!(queue.name, "projects/p/locations/l/queues/q");
+        assert_eq!(queue.rate_limits, None);
+        assert_eq!(queue.retry_config.unwrap().max_attempts, 10);
+    }
+
+    #[test]
+    fn rate_limits_roundtrip() {
+        let limits = RateLimits {
+            max_dispatches_per_second: 2.5,
+            max_burst_size: 10,
+            max_concurrent_dispatches: 100,
+        };
+        let proto = proto::RateLimits::from(limits.clone());
+        assert_eq!(proto.max_dispatches_per_second, 2.5);
+        assert_eq!(proto.max_burst_size, 10);
+        assert_eq!(proto.max_concurrent_dispatches, 100);
+        assert_eq!(RateLimits::from(proto), limits);
+    }
+
+    #[test]
+    fn retry_config_durations() {
+        let proto = proto::RetryConfig::from(retry_config());
+        assert_eq!(
+            proto.min_backoff,
+            Some(prost_types::Duration {
+                seconds: 1,
+                nanos: 500_000_000
+            })
+        );
+        assert_eq!(
+            proto.max_retry_duration.as_ref().map(|d| d.seconds),
+            Some(3600)
+        );
+        // unset durations are left to the service
+        assert_eq!(proto.max_backoff, None);
+        assert_eq!((proto.max_attempts, proto.max_doublings), (10, 4));
+        assert_eq!(RetryConfig::from(proto), retry_config());
+    }
+
+    #[test]
+    fn queue_from_proto() {
+        let queue = Queue::from(proto::Queue {
+            name: "projects/p/locations/l/queues/q".to_owned(),
+            retry_config: Some(proto::RetryConfig {
+                max_backoff: Some(prost_types::Duration {
+                    seconds: -1,
+                    nanos: 0,
+                }),
+                ..Default::default()
+            }),
+            state: proto::queue::State::Paused as i32,
+            ..Default::default()
+        });
+        assert_eq!(queue.state, QueueState::Paused);
+        // missing limits are defaults, negative durations are clamped to zero
+        assert_eq!(queue.rate_limits, RateLimits::default());
+        assert_eq!(queue.retry_config.max_backoff, Some(Duration::ZERO));
+        assert_eq!(queue.retry_config.min_backoff, None);
+        assert_eq!(queue.purge_time, None);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::Error;
+use hyper::{body::HttpBody, Body, HeaderMap, Request};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Task metadata Cloud Tasks sends in `X-CloudTasks-*` headers.
+///
+/// Headers are not authenticated for HTTP targets,
+/// use [`oidc_token`](super::TaskData::oidc_token) to verify the sender.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskHeaders {
+    /// Short name of the queue.
+    pub queue_name: String,
+    /// Short name of the task, generated by the service unless set on creation.
+    pub task_name: String,
+    /// Number of previous attempts, including ones not dispatched.
+    pub retry_count: u32,
+    /// Number of previous attempts that received a response.
+    pub execution_count: u32,
+    /// Time the task was scheduled to.
+    pub eta: SystemTime,
+    /// Status code of the previous attempt.
+    pub previous_response: Option<u16>,
+    /// Why the task is retried.
+    pub retry_reason: Option<String>,
+}
+
+/// Task received by the target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedTask<T> {
+    pub headers: TaskHeaders,
+    pub payload: T,
+}
+
+impl TaskHeaders {
+    /// Fails when required headers are missing or malformed.
+    pub fn from_headers(headers: &HeaderMap) -> crate::Result<Self> {
+        // value comes from the request, so out of range times are errors
+        let eta: f64 = parse(headers, "X-CloudTasks-TaskETA")?;
+        let eta = Duration::try_from_secs_f64(eta)
+            .ok()
+            .and_then(|eta| UNIX_EPOCH.checked_add(eta))
+            .ok_or_else(|| invalid_header("X-CloudTasks-TaskETA"))?;
+
+        Ok(TaskHeaders {
+            queue_name: parse(headers, "X-CloudTasks-QueueName")?,
+            task_name: parse(headers, "X-CloudTasks-TaskName")?,
+            retry_count: parse(headers, "X-CloudTasks-TaskRetryCount")?,
+            execution_count: parse(headers, "X-CloudTasks-TaskExecutionCount")?,
+            eta,
+            previous_response: parse(headers, "X-CloudTasks-TaskPreviousResponse").ok(),
+            retry_reason: parse(headers, "X-CloudTasks-TaskRetryReason").ok(),
+        })
+    }
+}
+
+/// Largest body [`receive_json_task`] accepts, the task size limit of Cloud Tasks.
+pub const MAX_TASK_BODY_SIZE: usize = 1024 * 1024;
+
+/// Validates task headers of the request and deserializes its JSON body.
+///
+/// Bodies over [`MAX_TASK_BODY_SIZE`] are rejected.
+pub async fn receive_json_task<T: serde::de::DeserializeOwned>(
+    request: Request<Body>,
+) -> crate::Result<ReceivedTask<T>> {
+    receive_json_task_with_limit(request, MAX_TASK_BODY_SIZE).await
+}
+
+/// Same as [`receive_json_task`], but rejects bodies over `max_body_size` bytes.
+pub async fn receive_json_task_with_limit<T: serde::de::DeserializeOwned>(
+    request: Request<Body>,
+    max_body_size: usize,
+) -> crate::Result<ReceivedTask<T>> {
+    let headers = TaskHeaders::from_headers(request.headers())?;
+    if let Ok(length) = parse::<u64>(request.headers(), "Content-Length") {
+        if length > max_body_size as u64 {
+            return Err(body_too_large(max_body_size));
+        }
+    }
+
+    // Content-Length may be absent, so the read is capped as well
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| Error::Transport(Box::new(e)))?;
+        if bytes.len() + chunk.len() > max_body_size {
+            return Err(body_too_large(max_body_size));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(ReceivedTask {
+        headers,
+        payload: serde_json::from_slice(&bytes)?,
+    })
+}
+
+fn parse<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> crate::Result<T> {
+    let value = headers
+        .get(name)
+        .ok_or_else(|| Error::InvalidInput(format!("{} header is missing", name)))?;
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| invalid_header(name))
+}
+
+fn invalid_header(name: &str) -> Error {
+    Error::InvalidInput(format!("{} header is malformed", name))
+}
+
+fn body_too_large(max_body_size: usize) -> Error {
+    Error::InvalidInput(format!("body is larger than {} bytes", max_body_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task_headers(eta: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-CloudTasks-QueueName", "queue".parse().unwrap());
+        headers.insert("X-CloudTasks-TaskName", "task".parse().unwrap());
+        headers.insert("X-CloudTasks-TaskRetryCount", "2".parse().unwrap());
+        headers.insert("X-CloudTasks-TaskExecutionCount", "1".parse().unwrap());
+        headers.insert("X-CloudTasks-TaskETA", eta.parse().unwrap());
+        headers
+    }
+
+    fn error_message(headers: &HeaderMap) -> String {
+        match TaskHeaders::from_headers(headers) {
+            Err(Error::InvalidInput(message)) => message,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(headers) => panic!("headers were accepted: {:?}", headers),
+        }
+    }
+
+    #[test]
+    fn parses_headers() {
+        let mut headers = task_headers("1600000000.5");
+        headers.insert("X-CloudTasks-TaskPreviousResponse", "503".parse().unwrap());
+        let parsed = TaskHeaders::from_headers(&headers).unwrap();
+        assert_eq!(
+            parsed,
+            TaskHeaders {
+                queue_name: "queue".to_owned(),
+                task_name: "task".to_owned(),
+                retry_count: 2,
+                execution_count: 1,
+                eta: UNIX_EPOCH + Duration::from_millis(1_600_000_000_500),
+                previous_response: Some(503),
+                retry_reason: None,
+            }
+        );
+    }
+
+    #[test]
+    fn missing_header() {
+        let mut headers = task_headers("0");
+        headers.remove("X-CloudTasks-TaskName");
+        assert_eq!(
+            error_message(&headers),
+            "X-CloudTasks-TaskName header is missing"
+        );
+    }
+
+    #[test]
+    fn malformed_header() {
+        let mut headers = task_headers("0");
+        headers.insert("X-CloudTasks-TaskRetryCount", "-1".parse().unwrap());
+        assert_eq!(
+            error_message(&headers),
+            "X-CloudTasks-TaskRetryCount header is malformed"
+        );
+
+        for eta in &["soon", "NaN", "inf", ""] {
+            assert_eq!(
+                error_message(&task_headers(eta)),
+                "X-CloudTasks-TaskETA header is malformed"
+            );
+        }
+    }
+
+    fn request(body: &'static str, content_length: Option<&str>) -> Request<Body> {
+        let mut request = Request::new(Body::from(body));
+        *request.headers_mut() = task_headers("0");
+        if let Some(length) = content_length {
+            request
+                .headers_mut()
+                .insert("Content-Length", length.parse().unwrap());
+        }
+        request
+    }
+
+    fn receive(request: Request<Body>, max_body_size: usize) -> crate::Result<Vec<u32>> {
+        futures::executor::block_on(receive_json_task_with_limit(request, max_body_size))
+            .map(|task| task.payload)
+    }
+
+    #[test]
+    fn receives_body_within_limit() {
+        assert_eq!(receive(request("[1,2]", Some("5")), 5).unwrap(), [1, 2]);
+        assert_eq!(receive(request("[1,2]", None), 5).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn rejects_large_body() {
+        let oversized = [
+            // declared length is checked before reading
+            request("[1,2]", Some("6")),
+            // without the header body is read up to the limit
+            request("[1,2,3]", None),
+        ];
+        for request in oversized {
+            match receive(request, 5) {
+                Err(Error::InvalidInput(message)) => {
+                    assert_eq!(message, "body is larger than 5 bytes")
+                }
+                result => panic!("unexpected result {:?}", result.map_err(|e| e.to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_eta() {
+        for eta in &["-1", "1e30", "1.8e19"] {
+            assert_eq!(
+                error_message(&task_headers(eta)),
+                "X-CloudTasks-TaskETA header is malformed"
+            );
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const FILE_EXTENSION: &str = "tts";
+
+/// Cache of synthesized audio, see
+/// [`RpcBuilder::tts_cache`](crate::google::RpcBuilder::tts_cache).
+///
+/// Recently used results are kept in memory. When a directory is set,
+/// results are also stored there, so they survive restarts.
+pub struct SynthesisCache {
+    memory: Mutex<Memory>,
+    directory: Option<Arc<Directory>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    disk_errors: AtomicU64,
+}
+
+/// Counters of cache usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of results held in memory.
+    pub memory_entries: usize,
+    /// Bytes of keys and audio held in memory.
+    pub memory_bytes: usize,
+    /// Bytes of files in cache directory, `0` when none is set.
+    pub disk_bytes: u64,
+    /// Failed reads and writes of cache files. Synthesis does not fail
+    /// because of them, results are synthesized or kept in memory only.
+    pub disk_errors: u64,
+}
+
+struct Memory {
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Keys by the tick of their last use, least recent first.
+    recency: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+struct Entry {
+    audio: Vec<u8>,
+    used: u64,
+}
+
+struct Directory {
+    path: PathBuf,
+    max_bytes: u64,
+    bytes: Mutex<u64>,
+    /// Makes names of temporary files unique between concurrent writes.
+    writes: AtomicU64,
+}
+
+impl SynthesisCache {
+    /// Creates cache holding up to `max_memory_bytes` of results in memory.
+    pub fn new(max_memory_bytes: usize) -> Self {
+        SynthesisCache {
+            memory: Mutex::new(Memory {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                bytes: 0,
+                max_bytes: max_memory_bytes,
+            }),
+            directory: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            disk_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Stores results in `path` as well, creating it if needed.
+    ///
+    /// When files take more than `max_bytes`, the oldest are removed.
+    pub fn directory(mut self, path: impl Into<PathBuf>, max_bytes: u64) -> crate::Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        let bytes = cache_files(&path)?.iter().map(|file| file.1).sum();
+        self.directory = Some(Arc::new(Directory {
+            path,
+            max_bytes,
+            bytes: Mutex::new(bytes),
+            writes: AtomicU64::new(0),
+        }));
+        Ok(self)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let memory = self.memory.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            memory_entries: memory.entries.len(),
+            memory_bytes: memory.bytes,
+            disk_bytes: self
+                .directory
+                .as_ref()
+                .map_or(0, |directory| *directory.bytes.lock().unwrap()),
+            disk_errors: self.disk_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) async fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let cached = self.memory.lock().unwrap().get(key);
+        let audio = match cached {
+            Some(audio) => Some(audio),
+            None => self.get_from_disk(key).await,
+        };
+
+        match audio {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        audio
+    }
+
+    /// Results found on disk are promoted to memory.
+    async fn get_from_disk(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let directory = self.directory.clone()?;
+        let file_key = key.to_vec();
+        match blocking(move || directory.get(&file_key)).await {
+            Ok(Some(audio)) => {
+                self.memory.lock().unwrap().insert(key, audio.clone());
+                Some(audio)
+            }
+            Ok(None) => None,
+            Err(_) => {
+                self.disk_errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Failing to persist result does not fail synthesis,
+    /// it is counted in [`CacheStats::disk_errors`].
+    pub(crate) async fn insert(&self, key: &[u8], audio: &[u8]) {
+        self.memory.lock().unwrap().insert(key, audio.to_vec());
+        if let Some(directory) = self.directory.clone() {
+            let (key, audio) = (key.to_vec(), audio.to_vec());
+            if blocking(move || directory.insert(&key, &audio))
+                .await
+                .is_err()
+            {
+                self.disk_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Runs file system work off async workers.
+async fn blocking<T, F>(work: F) -> crate::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> crate::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(io::Error::other)?
+}
+
+impl Memory {
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        let key = self.recency.remove(&entry.used)?;
+        entry.used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(entry.audio.clone())
+    }
+
+    fn insert(&mut self, key: &[u8], audio: Vec<u8>) {
+        let size = key.len() + audio.len();
+        if size > self.max_bytes {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some(previous) = self.entries.remove(key) {
+            self.recency.remove(&previous.used);
+            self.bytes -= key.len() + previous.audio.len();
+        }
+        self.entries.insert(
+            key.to_vec(),
+            Entry {
+                audio,
+                used: self.tick,
+            },
+        );
+        self.recency.insert(self.tick, key.to_vec());
+        self.bytes += size;
+
+        // evict least recently used results
+        while self.bytes > self.max_bytes {
+            let used = match self.recency.keys().next() {
+                Some(&used) => used,
+                None => break,
+            };
+            let key = self.recency.remove(&used).unwrap();
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= key.len() + entry.audio.len();
+            }
+        }
+    }
+}
+
+impl Directory {
+    fn file(&self, key: &[u8]) -> PathBuf {
+        self.path
+            .join(format!("{:016x}.{}", fnv1a(key), FILE_EXTENSION))
+    }
+
+    /// Files hold key length, key and audio, key is compared
+    /// on read to rule out hash collisions.
+    fn get(&self, key: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        let content = match fs::read(self.file(key)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if content.len() < 4 {
+            return Ok(None);
+        }
+        let key_length = u32::from_le_bytes([content[0], content[1], content[2], content[3]]);
+        let audio_start = 4 + key_length as usize;
+        if content.len() < audio_start || &content[4..audio_start] != key {
+            return Ok(None);
+        }
+        Ok(Some(content[audio_start..].to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], audio: &[u8]) -> crate::Result<()> {
+        let mut content = Vec::with_capacity(4 + key.len() + audio.len());
+        content.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        content.extend_from_slice(key);
+        content.extend_from_slice(audio);
+
+        // write to temporary file first, so readers never see partial content
+        let file = self.file(key);
+        let temporary = file.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            self.writes.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = fs::write(&temporary, &content) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e.into());
+        }
+
+        // size of the replaced file is only accurate while no other write
+        // can replace it, so it is read and replaced under the lock
+        let mut bytes = self.bytes.lock().unwrap();
+        let replaced = fs::metadata(&file).map_or(0, |metadata| metadata.len());
+        if let Err(e) = fs::rename(&temporary, &file) {
+            let _ = fs::remove_file(&temporary);
+            return Err(e.into());
+        }
+        *bytes = (*bytes + content.len() as u64).saturating_sub(replaced);
+        if *bytes > self.max_bytes {
+            *bytes = self.evict()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the oldest files until the rest fit into the limit,
+    /// returns the size of remaining files.
+    fn evict(&self) -> crate::Result<u64> {
+        let mut files = cache_files(&self.path)?;
+        files.sort_by_key(|file| file.2);
+
+        let mut bytes: u64 = files.iter().map(|file| file.1).sum();
+        for (path, length, _) in files {
+            if bytes <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(path)?;
+            bytes -= length;
+        }
+        Ok(bytes)
+    }
+}
+
+/// Lists path, length and modification time of cache files in `path`.
+fn cache_files(path: &Path) -> crate::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension() != Some(FILE_EXTENSION.as_ref()) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        files.push((path, metadata.len(), metadata.modified()?));
+    }
+    Ok(files)
+}
+
+/// 64 bit FNV-1a, stable across builds unlike std hashers.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(max_bytes: usize) -> Memory {
+        Memory {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tts-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut memory = memory(30);
+        memory.insert(b"a", vec![0; 9]);
+        memory.insert(b"b", vec![0; 9]);
+        memory.insert(b"c", vec![0; 9]);
+        assert_eq!(memory.bytes, 30);
+
+        // reading "a" makes "b" the least recent
+        assert!(memory.get(b"a").is_some());
+        memory.insert(b"d", vec![0; 9]);
+        assert!(memory.get(b"b").is_none());
+        assert_eq!(memory.bytes, 30);
+
+        // larger entry evicts as many as needed, least recent first
+        memory.insert(b"e", vec![0; 19]);
+        assert!(memory.get(b"c").is_none());
+        assert!(memory.get(b"a").is_none());
+        assert!(memory.get(b"d").is_some());
+        assert!(memory.get(b"e").is_some());
+        assert_eq!(memory.entries.len(), 2);
+        assert_eq!(memory.recency.len(), 2);
+        assert_eq!(memory.bytes, 30);
+    }
+
+    #[test]
+    fn replaced_entry_bytes() {
+        let mut memory = memory(100);
+        memory.insert(b"key", vec![0; 40]);
+        memory.insert(b"key", vec![0; 10]);
+        assert_eq!(memory.bytes, 13);
+        assert_eq!(memory.entries.len(), 1);
+        assert_eq!(memory.recency.len(), 1);
+        assert_eq!(memory.get(b"key"), Some(vec![0; 10]));
+    }
+
+    #[test]
+    fn oversized_entry_is_not_stored() {
+        let mut memory = memory(10);
+        memory.insert(b"a", vec![0; 5]);
+        memory.insert(b"b", vec![0; 10]);
+        assert!(memory.get(b"b").is_none());
+        assert!(memory.get(b"a").is_some());
+        assert_eq!(memory.bytes, 6);
+    }
+
+    #[test]
+    fn directory_roundtrip() {
+        let path = temporary_directory("roundtrip");
+        let cache = SynthesisCache::new(0).directory(&path, 1000).unwrap();
+        block_on(async {
+            assert_eq!(cache.get(b"key").await, None);
+            cache.insert(b"key", &[1, 2, 3]).await;
+            // memory holds nothing, so the result comes from disk
+            assert_eq!(cache.get(b"key").await, Some(vec![1, 2, 3]));
+        });
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.disk_errors), (1, 1, 0));
+        assert_eq!(stats.disk_bytes, 4 + 3 + 3);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_inserts_of_same_key() {
+        let path = temporary_directory("concurrent");
+        let cache = SynthesisCache::new(0).directory(&path, 1000).unwrap();
+        let audio: Vec<Vec<u8>> = (0..8).map(|i| vec![i; 10]).collect();
+        block_on(futures::future::join_all(
+            audio.iter().map(|audio| cache.insert(b"key", audio)),
+        ));
+        assert_eq!(cache.stats().disk_errors, 0);
+
+        let files = fs::read_dir(&path).unwrap().count();
+        assert_eq!(files, 1);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_inserts_keep_disk_bytes() {
+        let path = temporary_directory("disk-bytes");
+        let cache = SynthesisCache::new(0).directory(&path, 100_000).unwrap();
+        // keys are replaced many times with audio of different sizes
+        let inserts: Vec<(Vec<u8>, Vec<u8>)> = (0..64)
+            .map(|i| (vec![i % 4], vec![0; 10 + i as usize]))
+            .collect();
+        // writes run on blocking threads, in parallel
+        block_on(futures::future::join_all(
+            inserts.iter().map(|(key, audio)| cache.insert(key, audio)),
+        ));
+
+        let files = cache_files(&path).unwrap();
+        assert_eq!(files.len(), 4);
+        let stats = cache.stats();
+        assert_eq!(stats.disk_errors, 0);
+        assert_eq!(
+            stats.disk_bytes,
+            files.iter().map(|file| file.1).sum::<u64>()
+        );
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn directory_evicts_oldest_files() {
+        let path = temporary_directory("evict");
+        let cache = SynthesisCache::new(0).directory(&path, 40).unwrap();
+        block_on(async {
+            for key in [b"a", b"b", b"c"].iter() {
+                cache.insert(*key, &[0; 15]).await;
+            }
+        });
+        // each file takes 4 + 1 + 15 bytes
+        assert_eq!(cache.stats().disk_bytes, 40);
+        assert_eq!(cache_files(&path).unwrap().len(), 2);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
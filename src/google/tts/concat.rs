@@ -0,0 +1,634 @@
+use super::AudioEncoding;
+use crate::audio::{detect_pcm, encode_wav};
+
+/// Joins audio synthesized in parts into one playable file.
+pub(crate) fn concat(encoding: AudioEncoding, parts: Vec<Vec<u8>>) -> crate::Result<Vec<u8>> {
+    if parts.len() == 1 {
+        return Ok(parts.into_iter().next().unwrap());
+    }
+    match encoding {
+        AudioEncoding::Linear16 => concat_wav(&parts),
+        AudioEncoding::Mp3 => Ok(concat_mp3(&parts)),
+        AudioEncoding::OggOpus => concat_ogg(&parts),
+        AudioEncoding::Unspecified => Ok(parts.concat()),
+    }
+}
+
+// --------------------------------
+// LINEAR16
+// --------------------------------
+
+/// Every part carries its own WAV header, samples are joined
+/// under a single header with updated sizes.
+fn concat_wav(parts: &[Vec<u8>]) -> crate::Result<Vec<u8>> {
+    let mut format = None;
+    let mut data = vec![];
+    for part in parts {
+        let pcm = detect_pcm(part)?;
+        format = format.or(pcm.format);
+        data.extend_from_slice(pcm.data);
+    }
+    Ok(match format {
+        Some(format) => encode_wav(format, &data),
+        None => data,
+    })
+}
+
+// --------------------------------
+// MP3
+// --------------------------------
+
+/// MP3 frames can be joined as is, only ID3 tags and info frames
+/// holding the duration of a single part are left out.
+fn concat_mp3(parts: &[Vec<u8>]) -> Vec<u8> {
+    let mut joined = vec![];
+    for (index, part) in parts.iter().enumerate() {
+        let frames = skip_id3(part);
+        if index == 0 {
+            joined.extend_from_slice(&part[..part.len() - frames.len()]);
+        }
+        joined.extend_from_slice(skip_info_frame(frames));
+    }
+    joined
+}
+
+fn skip_id3(part: &[u8]) -> &[u8] {
+    if part.len() < 10 || !part.starts_with(b"ID3") {
+        return part;
+    }
+    // size is syncsafe integer, seven bits per byte
+    let size = part[6..10]
+        .iter()
+        .fold(0usize, |size, &byte| (size << 7) | (byte & 0x7f) as usize);
+    let footer = if part[5] & 0x10 != 0 { 10 } else { 0 };
+    &part[(10 + size + footer).min(part.len())..]
+}
+
+/// Drops leading Xing/Info frame of MPEG layer III stream.
+fn skip_info_frame(frames: &[u8]) -> &[u8] {
+    const MPEG1_BITRATES: [usize; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const MPEG2_BITRATES: [usize; 15] =
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const SAMPLE_RATES: [usize; 3] = [44100, 48000, 32000];
+
+    if frames.len() < 4 || frames[0] != 0xff || frames[1] & 0xe0 != 0xe0 {
+        return frames;
+    }
+    let version = (frames[1] >> 3) & 0b11;
+    let layer = (frames[1] >> 1) & 0b11;
+    let bitrate_index = (frames[2] >> 4) as usize;
+    let sample_rate_index = ((frames[2] >> 2) & 0b11) as usize;
+    let padding = ((frames[2] >> 1) & 1) as usize;
+    // only layer III with known bitrate and sample rate
+    if version == 0b01
+        || layer != 0b01
+        || bitrate_index == 0
+        || bitrate_index == 15
+        || sample_rate_index == 3
+    {
+        return frames;
+    }
+
+    let length = match version {
+        // MPEG 1
+        0b11 => 144_000 * MPEG1_BITRATES[bitrate_index] / SAMPLE_RATES[sample_rate_index] + padding,
+        // MPEG 2
+        0b10 => {
+            72_000 * MPEG2_BITRATES[bitrate_index] / (SAMPLE_RATES[sample_rate_index] / 2) + padding
+        }
+        // MPEG 2.5
+        _ => {
+            72_000 * MPEG2_BITRATES[bitrate_index] / (SAMPLE_RATES[sample_rate_index] / 4) + padding
+        }
+    };
+    let frame = &frames[..length.min(frames.len())];
+    let is_info = frame
+        .windows(4)
+        .take(64)
+        .any(|tag| tag == b"Xing" || tag == b"Info");
+    if is_info {
+        &frames[frame.len()..]
+    } else {
+        frames
+    }
+}
+
+// --------------------------------
+// OGG_OPUS
+// --------------------------------
+
+/// Ogg page header length without segment table.
+const PAGE_HEADER: usize = 27;
+const CONTINUED_PACKET: u8 = 0x01;
+const FIRST_PAGE: u8 = 0x02;
+const LAST_PAGE: u8 = 0x04;
+
+struct Page<'a> {
+    header_type: u8,
+    granule_position: u64,
+    serial: u32,
+    segments: &'a [u8],
+    body: &'a [u8],
+}
+
+/// Pages are ended once their body reaches this size.
+const PAGE_BODY: usize = 4096;
+/// Samples per second of granule positions, whatever the input rate.
+const OPUS_RATE: u64 = 48_000;
+
+/// Audio packets of all parts are joined into a single logical stream
+/// under headers of the first part, and paginated anew.
+///
+/// Granule positions are counted from packet durations, since every part
+/// trims its end and starts with its own pre-skip. Leading packets of later
+/// parts that lie within their pre-skip are dropped, the end of the last
+/// part is trimmed as in the source.
+fn concat_ogg(parts: &[Vec<u8>]) -> crate::Result<Vec<u8>> {
+    let mut writer = None;
+    let mut granule = 0u64;
+    let mut end_trim = 0;
+
+    for (part_index, part) in parts.iter().enumerate() {
+        let pages = ogg_pages(part)?;
+        let serial = pages.first().map_or(0, |page| page.serial);
+        let last_granule = pages
+            .iter()
+            .rev()
+            .map(|page| page.granule_position)
+            .find(|&granule| granule != u64::MAX)
+            .unwrap_or(0);
+        let mut packets = ogg_packets(&pages).into_iter();
+        let (head, tags) = match (packets.next(), packets.next()) {
+            (Some(head), Some(tags)) if head.starts_with(b"OpusHead") && head.len() >= 19 => {
+                (head, tags)
+            }
+            _ => {
+                return Err(crate::Error::Decode(
+                    "ogg stream without opus headers".to_owned(),
+                ))
+            }
+        };
+        let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u64;
+
+        let writer = writer.get_or_insert_with(|| {
+            let mut writer = OggWriter::new(serial);
+            // headers take pages of their own
+            writer.packet(&head, 0);
+            writer.flush_page();
+            writer.packet(&tags, 0);
+            writer.flush_page();
+            writer
+        });
+
+        // first part keeps its pre-skip, it is signalled in the header
+        let mut skipped = if part_index == 0 { pre_skip } else { 0 };
+        let mut decoded = 0;
+        for packet in packets {
+            let duration = opus_packet_samples(&packet);
+            decoded += duration;
+            if skipped < pre_skip && skipped + duration <= pre_skip {
+                skipped += duration;
+                continue;
+            }
+            granule += duration;
+            writer.packet(&packet, granule);
+        }
+        end_trim = decoded.saturating_sub(last_granule);
+    }
+
+    Ok(match writer {
+        Some(writer) => writer.finish(granule.saturating_sub(end_trim)),
+        None => vec![],
+    })
+}
+
+/// Number of 48 kHz samples a packet decodes to, from its TOC byte.
+fn opus_packet_samples(packet: &[u8]) -> u64 {
+    let toc = match packet.first() {
+        Some(&toc) => toc,
+        None => return 0,
+    };
+    let config = (toc >> 3) as usize;
+    // frame duration in units of 2.5 ms
+    let frame = match config {
+        // SILK only: 10, 20, 40, 60 ms
+        0..=11 => [4, 8, 16, 24][config % 4],
+        // hybrid: 10, 20 ms
+        12..=15 => [4, 8][config % 2],
+        // CELT only: 2.5, 5, 10, 20 ms
+        _ => [1, 2, 4, 8][config % 4],
+    };
+    let frames = match toc & 0b11 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| count & 0x3f) as u64,
+    };
+    frames * frame * OPUS_RATE / 400
+}
+
+/// Reassembles packets of a single logical stream, a packet
+/// left unfinished by the last page is dropped.
+fn ogg_packets(pages: &[Page]) -> Vec<Vec<u8>> {
+    let mut packets = vec![];
+    let mut packet = vec![];
+    for page in pages {
+        let mut body = page.body;
+        for &segment in page.segments {
+            let (data, rest) = body.split_at(segment as usize);
+            packet.extend_from_slice(data);
+            body = rest;
+            if segment < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+        }
+    }
+    packets
+}
+
+/// Paginates packets of a new logical stream.
+struct OggWriter {
+    stream: Vec<u8>,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    body: Vec<u8>,
+    /// Granule position of the last packet finished on the current page.
+    granule: Option<u64>,
+    /// Current page starts with the rest of a packet.
+    continued: bool,
+}
+
+impl OggWriter {
+    fn new(serial: u32) -> OggWriter {
+        OggWriter {
+            stream: vec![],
+            serial,
+            sequence: 0,
+            segments: vec![],
+            body: vec![],
+            granule: None,
+            continued: false,
+        }
+    }
+
+    /// Adds packet ending at `granule`. Full page is written only when
+    /// the next packet comes, so that the last page is left to [`finish`](Self::finish).
+    fn packet(&mut self, mut packet: &[u8], granule: u64) {
+        if self.body.len() >= PAGE_BODY {
+            self.flush_page();
+        }
+        loop {
+            // packet goes on on the next page
+            if self.segments.len() == 255 {
+                self.write_page(0);
+                self.continued = true;
+            }
+            let segment = packet.len().min(255);
+            self.segments.push(segment as u8);
+            self.body.extend_from_slice(&packet[..segment]);
+            packet = &packet[segment..];
+            // segment shorter than 255 bytes ends the packet
+            if segment < 255 {
+                break;
+            }
+        }
+        self.granule = Some(granule);
+    }
+
+    fn flush_page(&mut self) {
+        if !self.segments.is_empty() {
+            self.write_page(0);
+        }
+    }
+
+    /// Writes the last page, with granule position trimming the end of stream.
+    fn finish(mut self, granule: u64) -> Vec<u8> {
+        // without audio packets an empty page marks the end
+        if !self.segments.is_empty() {
+            self.granule = Some(granule);
+        }
+        self.write_page(LAST_PAGE);
+        self.stream
+    }
+
+    fn write_page(&mut self, flags: u8) {
+        let mut header_type = flags;
+        if self.continued {
+            header_type |= CONTINUED_PACKET;
+        }
+        if self.sequence == 0 {
+            header_type |= FIRST_PAGE;
+        }
+        write_page(
+            &mut self.stream,
+            Page {
+                header_type,
+                // pages without finished packet carry -1
+                granule_position: self.granule.take().unwrap_or(u64::MAX),
+                serial: self.serial,
+                segments: &self.segments,
+                body: &self.body,
+            },
+            self.sequence,
+        );
+        self.sequence += 1;
+        self.segments.clear();
+        self.body.clear();
+        self.continued = false;
+    }
+}
+
+fn ogg_pages(mut stream: &[u8]) -> crate::Result<Vec<Page<'_>>> {
+    let malformed = || crate::Error::Decode("malformed ogg stream".to_owned());
+
+    let mut pages = vec![];
+    while !stream.is_empty() {
+        if stream.len() < PAGE_HEADER || !stream.starts_with(b"OggS") {
+            return Err(malformed());
+        }
+        let segments_end = PAGE_HEADER + stream[26] as usize;
+        let segments = stream
+            .get(PAGE_HEADER..segments_end)
+            .ok_or_else(malformed)?;
+        let body_end = segments_end
+            + segments
+                .iter()
+                .map(|&segment| segment as usize)
+                .sum::<usize>();
+        let body = stream.get(segments_end..body_end).ok_or_else(malformed)?;
+
+        let mut granule_position = [0; 8];
+        granule_position.copy_from_slice(&stream[6..14]);
+        let mut serial = [0; 4];
+        serial.copy_from_slice(&stream[14..18]);
+        pages.push(Page {
+            header_type: stream[5],
+            granule_position: u64::from_le_bytes(granule_position),
+            serial: u32::from_le_bytes(serial),
+            segments,
+            body,
+        });
+        stream = &stream[body_end..];
+    }
+    Ok(pages)
+}
+
+fn write_page(target: &mut Vec<u8>, page: Page, sequence: u32) {
+    let start = target.len();
+    target.extend_from_slice(b"OggS");
+    target.push(0);
+    target.push(page.header_type);
+    target.extend_from_slice(&page.granule_position.to_le_bytes());
+    target.extend_from_slice(&page.serial.to_le_bytes());
+    target.extend_from_slice(&sequence.to_le_bytes());
+    // checksum is computed with its own field zeroed
+    target.extend_from_slice(&[0; 4]);
+    target.push(page.segments.len() as u8);
+    target.extend_from_slice(page.segments);
+    target.extend_from_slice(page.body);
+
+    let checksum = ogg_crc(&target[start..]);
+    target[start + 22..start + 26].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// CRC-32 with polynomial 0x04c11db7, no reflection and zero initial value.
+fn ogg_crc(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u32) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::PcmFormat;
+
+    // --------------------------------
+    // LINEAR16
+    // --------------------------------
+
+    #[test]
+    fn wav_parts_share_header() {
+        let format = PcmFormat {
+            sample_rate: 24000,
+            channels: 1,
+            bits_per_sample: 16,
+        };
+        let parts = vec![
+            encode_wav(format, &[1, 2, 3, 4]),
+            encode_wav(format, &[5, 6]),
+        ];
+        assert_eq!(
+            concat(AudioEncoding::Linear16, parts).unwrap(),
+            encode_wav(format, &[1, 2, 3, 4, 5, 6])
+        );
+    }
+
+    // --------------------------------
+    // MP3
+    // --------------------------------
+
+    /// MPEG 1 layer III, 128 kbps, 44.1 kHz
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x64];
+    const FRAME_LENGTH: usize = 417;
+
+    fn frame(fill: u8) -> Vec<u8> {
+        let mut frame = FRAME_HEADER.to_vec();
+        frame.resize(FRAME_LENGTH, fill);
+        frame
+    }
+
+    fn info_frame(tag: &[u8; 4]) -> Vec<u8> {
+        let mut frame = frame(0);
+        // tag follows side information of stereo stream
+        frame[36..40].copy_from_slice(tag);
+        frame
+    }
+
+    fn id3() -> Vec<u8> {
+        // empty tag of syncsafe size 0x81 bytes
+        let mut tag = b"ID3\x04\x00\x00\x00\x00\x01\x01".to_vec();
+        tag.resize(10 + 0x81, 0);
+        tag
+    }
+
+    #[test]
+    fn info_frame_is_skipped() {
+        let mut part = info_frame(b"Xing");
+        part.extend(frame(1));
+        assert_eq!(skip_info_frame(&part), &frame(1)[..]);
+
+        let mut part = info_frame(b"Info");
+        part.extend(frame(1));
+        assert_eq!(skip_info_frame(&part), &frame(1)[..]);
+
+        let part = [frame(1), frame(2)].concat();
+        assert_eq!(skip_info_frame(&part), &part[..]);
+
+        // layer II frame is left as is
+        let mut part = info_frame(b"Info");
+        part[1] = 0xfd;
+        assert_eq!(skip_info_frame(&part), &part[..]);
+    }
+
+    #[test]
+    fn id3_tag_is_skipped() {
+        let part = [id3(), frame(1)].concat();
+        assert_eq!(skip_id3(&part), &frame(1)[..]);
+        assert_eq!(skip_id3(&frame(1)), &frame(1)[..]);
+    }
+
+    #[test]
+    fn mp3_parts_keep_first_tag_only() {
+        let parts = vec![
+            [id3(), info_frame(b"Info"), frame(1)].concat(),
+            [id3(), info_frame(b"Xing"), frame(2), frame(3)].concat(),
+            frame(4),
+        ];
+        assert_eq!(
+            concat(AudioEncoding::Mp3, parts).unwrap(),
+            [id3(), frame(1), frame(2), frame(3), frame(4)].concat()
+        );
+    }
+
+    // --------------------------------
+    // OGG_OPUS
+    // --------------------------------
+
+    /// OpusHead page with pre-skip of 312 samples.
+    const HEAD_PAGE: &str = "4f6767530002000000000000000078563412000000003d11c0bb\
+                             01134f707573486561640101380180bb0000000000";
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x01".to_vec();
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        head
+    }
+
+    /// Stream of a single part, every audio packet on a page of its own.
+    fn opus_stream(pre_skip: u16, packets: &[Vec<u8>], end_trim: u64) -> Vec<u8> {
+        let mut writer = OggWriter::new(7);
+        writer.packet(&opus_head(pre_skip), 0);
+        writer.flush_page();
+        writer.packet(b"OpusTags\x00\x00\x00\x00\x00\x00\x00\x00", 0);
+        writer.flush_page();
+        let mut granule = 0;
+        for packet in packets {
+            granule += opus_packet_samples(packet);
+            writer.flush_page();
+            writer.packet(packet, granule);
+        }
+        writer.finish(granule - end_trim)
+    }
+
+    fn granules(stream: &[u8]) -> Vec<u64> {
+        ogg_pages(stream)
+            .unwrap()
+            .iter()
+            .map(|page| page.granule_position)
+            .collect()
+    }
+
+    #[test]
+    fn crc_of_known_page() {
+        assert_eq!(ogg_crc(b"123456789"), 0x89a1_897f);
+
+        let page = hex(HEAD_PAGE);
+        let mut zeroed = page.clone();
+        zeroed[22..26].copy_from_slice(&[0; 4]);
+        assert_eq!(ogg_crc(&zeroed), 0xbbc0_113d);
+
+        let mut written = vec![];
+        let pages = ogg_pages(&page).unwrap();
+        assert_eq!(pages[0].granule_position, 0);
+        assert_eq!(pages[0].serial, 0x1234_5678);
+        write_page(&mut written, ogg_pages(&page).unwrap().remove(0), 0);
+        assert_eq!(written, page);
+    }
+
+    #[test]
+    fn packet_durations() {
+        // CELT 20 ms, single frame
+        assert_eq!(opus_packet_samples(&[0xf8, 0]), 960);
+        // SILK 60 ms, two frames
+        assert_eq!(opus_packet_samples(&[0x19, 0]), 5760);
+        // hybrid 10 ms, three frames in code 3 packet
+        assert_eq!(opus_packet_samples(&[0x63, 0x03]), 1440);
+        // CELT 2.5 ms
+        assert_eq!(opus_packet_samples(&[0x80]), 120);
+        assert_eq!(opus_packet_samples(&[]), 0);
+    }
+
+    #[test]
+    fn ogg_granules_follow_packet_durations() {
+        let packets = vec![vec![0xf8, 1], vec![0xf8, 2], vec![0xf8, 3]];
+        let parts = vec![
+            opus_stream(312, &packets, 500),
+            opus_stream(312, &packets, 500),
+            opus_stream(312, &packets, 100),
+        ];
+        let joined = concat(AudioEncoding::OggOpus, parts).unwrap();
+
+        // trimmed ends of first parts are played, only the end
+        // of the last part is trimmed
+        assert_eq!(granules(&joined), vec![0, 0, 9 * 960 - 100]);
+        let pages = ogg_pages(&joined).unwrap();
+        assert_eq!(pages[0].header_type, FIRST_PAGE);
+        assert_eq!(pages[2].header_type, LAST_PAGE);
+        assert!(pages.iter().all(|page| page.serial == 7));
+        assert_eq!(ogg_packets(&pages).len(), 2 + 9);
+        assert_eq!(pages[0].body, &opus_head(312)[..]);
+    }
+
+    #[test]
+    fn ogg_pre_skip_of_later_parts_is_dropped() {
+        // 2.5 ms packets, first two lie within pre-skip of 312 samples
+        let packets: Vec<Vec<u8>> = (0..4).map(|i| vec![0x80, i]).collect();
+        let parts = vec![opus_stream(312, &packets, 0), opus_stream(312, &packets, 0)];
+        let joined = concat(AudioEncoding::OggOpus, parts).unwrap();
+
+        let pages = ogg_pages(&joined).unwrap();
+        let packets = ogg_packets(&pages);
+        assert_eq!(packets.len(), 2 + 4 + 2);
+        assert_eq!(&packets[6..], &[vec![0x80, 2], vec![0x80, 3]]);
+        assert_eq!(granules(&joined).last(), Some(&(6 * 120)));
+    }
+
+    #[test]
+    fn ogg_packets_span_pages() {
+        let large = vec![0xf8; 255 * 600];
+        let parts = vec![
+            opus_stream(0, &[vec![0xf8, 1]], 0),
+            opus_stream(0, std::slice::from_ref(&large), 0),
+        ];
+        let joined = concat(AudioEncoding::OggOpus, parts).unwrap();
+
+        let pages = ogg_pages(&joined).unwrap();
+        // small packet and start of the large one, then its rest
+        assert_eq!(pages.len(), 5);
+        assert_eq!(pages[2].segments.len(), 255);
+        assert_eq!(pages[2].granule_position, 960);
+        assert_eq!(pages[3].granule_position, u64::MAX);
+        assert_eq!(pages[3].header_type, CONTINUED_PACKET);
+        assert_eq!(pages[4].header_type, CONTINUED_PACKET | LAST_PAGE);
+        assert_eq!(granules(&joined).last(), Some(&(2 * 960)));
+        assert_eq!(ogg_packets(&pages)[3], large);
+    }
+}
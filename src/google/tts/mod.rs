@@ -0,0 +1,505 @@
+crate::rpc_service!(
+    TtsClient,
+    "texttospeech",
+    "https://www.googleapis.com/auth/cloud-platform";
+    voices: Arc<Mutex<HashMap<String, CachedVoices>>>,
+    cache: Option<Arc<SynthesisCache>>
+);
+
+mod cache;
+mod concat;
+mod split;
+mod ssml;
+pub use cache::*;
+pub use ssml::*;
+
+use super::generated::google::cloud::texttospeech::v1 as proto;
+use super::generated::google::cloud::texttospeech::v1::*;
+pub use super::generated::google::cloud::texttospeech::v1::{
+    AudioConfig, SsmlVoiceGender, VoiceSelectionParams,
+};
+use futures::{StreamExt, TryStreamExt};
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Largest input accepted by a single synthesis request,
+/// see [`synthesize_long`] for longer ones.
+pub const MAX_INPUT_BYTES: usize = 5000;
+
+/// How long voices returned by [`list_voices`] are reused before
+/// the service is asked again.
+pub const VOICES_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Voices along with the time they were received.
+type CachedVoices = (Instant, Vec<Voice>);
+
+/// Usage of the cache of the client set up by
+/// [`RpcBuilder::initialize_tts`](crate::google::RpcBuilder::initialize_tts),
+/// `None` if it has no cache.
+pub fn cache_stats() -> Option<CacheStats> {
+    service().ok()?.cache_stats()
+}
+
+fn default_config() -> AudioConfig {
+    AudioConfig {
+        audio_encoding: 1,
+        speaking_rate: 1.2,
+        pitch: 1.0,
+        volume_gain_db: 0.0,
+        sample_rate_hertz: 8000,
+        effects_profile_id: vec![],
+    }
+}
+
+fn default_voice_params() -> VoiceSelectionParams {
+    VoiceSelectionParams {
+        language_code: "ru".to_string(),
+        name: "ru-RU-Wavenet-C".to_string(),
+        // Unspecified
+        ssml_gender: 2,
+    }
+}
+
+/// Text to synthesize.
+///
+/// Strings are not converted into input, callers choose explicitly
+/// between [`text`](Self::text), spoken as is, and [`ssml`](Self::ssml).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SynthesisInput {
+    /// Plain text, spoken as is.
+    Text(String),
+    /// Complete SSML document, see [`SsmlBuilder`].
+    Ssml(String),
+}
+
+impl SynthesisInput {
+    pub fn text(text: impl Into<String>) -> SynthesisInput {
+        SynthesisInput::Text(text.into())
+    }
+
+    pub fn ssml(document: impl Into<String>) -> SynthesisInput {
+        SynthesisInput::Ssml(document.into())
+    }
+
+    /// Wraps SSML content into `<speak>`, content is not escaped.
+    pub fn ssml_fragment(fragment: &str) -> SynthesisInput {
+        SynthesisInput::Ssml(format!("<speak>{}</speak>", fragment))
+    }
+}
+
+impl From<SynthesisInput> for proto::SynthesisInput {
+    fn from(input: SynthesisInput) -> Self {
+        let input_source = match input {
+            SynthesisInput::Text(text) => synthesis_input::InputSource::Text(text),
+            SynthesisInput::Ssml(ssml) => synthesis_input::InputSource::Ssml(ssml),
+        };
+        proto::SynthesisInput {
+            input_source: Some(input_source),
+        }
+    }
+}
+
+/// Voice supported by the service.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice {
+    /// Name of the voice, e.g. `ru-RU-Wavenet-C`.
+    pub name: String,
+    /// BCP-47 language codes the voice supports.
+    pub language_codes: Vec<String>,
+    pub gender: SsmlVoiceGender,
+    pub natural_sample_rate_hertz: i32,
+}
+
+/// Technology behind a voice, as encoded in its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoiceType {
+    Standard,
+    Wavenet,
+    Neural2,
+    Studio,
+    Polyglot,
+    News,
+    Other(String),
+}
+
+impl Voice {
+    pub fn voice_type(&self) -> VoiceType {
+        // names look like `ru-RU-Wavenet-C`
+        match self.name.split('-').nth(2) {
+            Some("Standard") => VoiceType::Standard,
+            Some("Wavenet") => VoiceType::Wavenet,
+            Some("Neural2") => VoiceType::Neural2,
+            Some("Studio") => VoiceType::Studio,
+            Some("Polyglot") => VoiceType::Polyglot,
+            Some("News") => VoiceType::News,
+            other => VoiceType::Other(other.unwrap_or_default().to_owned()),
+        }
+    }
+
+    /// Whether the voice speaks `language`, either exactly (`ru-RU`)
+    /// or as a regional variant of it (`ru`).
+    pub fn supports_language(&self, language: &str) -> bool {
+        self.language_codes.iter().any(|code| {
+            code.eq_ignore_ascii_case(language)
+                || (code.len() > language.len()
+                    && code.as_bytes()[language.len()] == b'-'
+                    && code[..language.len()].eq_ignore_ascii_case(language))
+        })
+    }
+
+    /// Parameters selecting exactly this voice for synthesis.
+    pub fn selection_params(&self) -> VoiceSelectionParams {
+        VoiceSelectionParams {
+            language_code: self.language_codes.first().cloned().unwrap_or_default(),
+            name: self.name.clone(),
+            ssml_gender: self.gender as i32,
+        }
+    }
+}
+
+impl From<proto::Voice> for Voice {
+    fn from(voice: proto::Voice) -> Self {
+        Voice {
+            gender: SsmlVoiceGender::from_i32(voice.ssml_gender)
+                .unwrap_or(SsmlVoiceGender::Unspecified),
+            name: voice.name,
+            language_codes: voice.language_codes,
+            natural_sample_rate_hertz: voice.natural_sample_rate_hertz,
+        }
+    }
+}
+
+/// Picks the first voice, in name order, matching every given criterion.
+pub fn select_voice<'a>(
+    voices: &'a [Voice],
+    language: &str,
+    gender: Option<SsmlVoiceGender>,
+    voice_type: Option<VoiceType>,
+) -> Option<&'a Voice> {
+    voices
+        .iter()
+        .filter(|voice| voice.supports_language(language))
+        .filter(|voice| gender.is_none() || gender == Some(voice.gender))
+        .filter(|voice| voice_type.is_none() || voice_type == Some(voice.voice_type()))
+        .min_by(|a, b| a.name.cmp(&b.name))
+}
+
+/// Voices received for `language` less than [`VOICES_CACHE_TTL`] before `now`.
+fn fresh_voices(
+    cache: &HashMap<String, CachedVoices>,
+    language: &str,
+    now: Instant,
+) -> Option<Vec<Voice>> {
+    let (received, voices) = cache.get(language)?;
+    if now.saturating_duration_since(*received) < VOICES_CACHE_TTL {
+        Some(voices.clone())
+    } else {
+        None
+    }
+}
+
+/// Lists voices supporting `language`, or all voices when it is `None`.
+///
+/// Results are cached for [`VOICES_CACHE_TTL`], every client
+/// keeps its own cache.
+pub async fn list_voices(language: Option<&str>) -> crate::Result<Vec<Voice>> {
+    service()?.list_voices(language).await
+}
+
+/// Looks up a voice by language, gender and type,
+/// see [`select_voice`].
+pub async fn find_voice(
+    language: &str,
+    gender: Option<SsmlVoiceGender>,
+    voice_type: Option<VoiceType>,
+) -> crate::Result<Option<Voice>> {
+    service()?.find_voice(language, gender, voice_type).await
+}
+
+/// Strings have to be wrapped with [`SynthesisInput::text`] or
+/// [`SynthesisInput::ssml`], [`SsmlBuilder`] is accepted as is.
+pub async fn synthesize(
+    input: impl Into<SynthesisInput>,
+    audio_config: Option<AudioConfig>,
+    voice_params: Option<VoiceSelectionParams>,
+) -> crate::Result<Vec<u8>> {
+    service()?
+        .synthesize(input, audio_config, voice_params)
+        .await
+}
+
+/// Synthesizes input of any length. It is split into parts
+/// of at most [`MAX_INPUT_BYTES`], up to `parallelism` of which are
+/// synthesized at once, and resulting audio is joined.
+pub async fn synthesize_long(
+    input: impl Into<SynthesisInput>,
+    audio_config: Option<AudioConfig>,
+    voice_params: Option<VoiceSelectionParams>,
+    parallelism: usize,
+) -> crate::Result<Vec<u8>> {
+    service()?
+        .synthesize_long(input, audio_config, voice_params, parallelism)
+        .await
+}
+
+impl TtsClient {
+    pub(crate) fn with_cache(mut self, cache: Option<Arc<SynthesisCache>>) -> TtsClient {
+        self.cache = cache;
+        self
+    }
+
+    /// Usage of the cache set by [`RpcBuilder::tts_cache`](crate::google::RpcBuilder::tts_cache),
+    /// `None` if the client has no cache.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|cache| cache.stats())
+    }
+
+    pub async fn list_voices(&self, language: Option<&str>) -> crate::Result<Vec<Voice>> {
+        let language = language.unwrap_or_default().to_owned();
+
+        // --------------------------------
+        // reuse voices received recently
+        // --------------------------------
+        if let Some(voices) = fresh_voices(&self.voices.lock().unwrap(), &language, Instant::now())
+        {
+            return Ok(voices);
+        }
+
+        // --------------------------------
+        // retrieve token and construct channel
+        // --------------------------------
+        let token = self.authorization().await?;
+
+        let mut service = text_to_speech_client::TextToSpeechClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        // --------------------------------
+        // send request
+        // --------------------------------
+        let response = service
+            .list_voices(ListVoicesRequest {
+                language_code: language.clone(),
+            })
+            .await?;
+
+        // --------------------------------
+        // take required result
+        // --------------------------------
+        let mut voices: Vec<Voice> = response
+            .into_inner()
+            .voices
+            .into_iter()
+            .map(Voice::from)
+            .collect();
+        voices.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.voices
+            .lock()
+            .unwrap()
+            .insert(language, (Instant::now(), voices.clone()));
+        Ok(voices)
+    }
+
+    pub async fn find_voice(
+        &self,
+        language: &str,
+        gender: Option<SsmlVoiceGender>,
+        voice_type: Option<VoiceType>,
+    ) -> crate::Result<Option<Voice>> {
+        let voices = self.list_voices(Some(language)).await?;
+        Ok(select_voice(&voices, language, gender, voice_type).cloned())
+    }
+
+    pub async fn synthesize(
+        &self,
+        input: impl Into<SynthesisInput>,
+        audio_config: Option<AudioConfig>,
+        voice_params: Option<VoiceSelectionParams>,
+    ) -> crate::Result<Vec<u8>> {
+        let audio_config = audio_config.unwrap_or_else(default_config);
+        let voice_params = voice_params.unwrap_or_else(default_voice_params);
+
+        // --------------------------------
+        // construct request
+        // --------------------------------
+        let request = SynthesizeSpeechRequest {
+            audio_config: Some(audio_config),
+            input: Some(input.into().into()),
+            voice: Some(voice_params),
+        };
+
+        // --------------------------------
+        // reuse result of identical request
+        // --------------------------------
+        let cache = self.cache.as_ref().map(|cache| {
+            // encoded request covers input, voice and audio config
+            let mut key = Vec::with_capacity(request.encoded_len());
+            let _ = request.encode(&mut key);
+            (cache, key)
+        });
+        if let Some((cache, key)) = &cache {
+            if let Some(audio) = cache.get(key).await {
+                return Ok(audio);
+            }
+        }
+
+        // --------------------------------
+        // retrieve token and construct channel
+        // --------------------------------
+        let token = self.authorization().await?;
+
+        let mut service = text_to_speech_client::TextToSpeechClient::with_interceptor(
+            self.channel.clone(),
+            crate::rpc::interceptor(token, vec![]),
+        );
+
+        // --------------------------------
+        // send request
+        // --------------------------------
+        let response = service.synthesize_speech(request).await?;
+
+        // --------------------------------
+        // take required result
+        // --------------------------------
+        let audio = response.into_inner().audio_content;
+        if let Some((cache, key)) = &cache {
+            cache.insert(key, &audio).await;
+        }
+        Ok(audio)
+    }
+
+    pub async fn synthesize_long(
+        &self,
+        input: impl Into<SynthesisInput>,
+        audio_config: Option<AudioConfig>,
+        voice_params: Option<VoiceSelectionParams>,
+        parallelism: usize,
+    ) -> crate::Result<Vec<u8>> {
+        let audio_config = audio_config.unwrap_or_else(default_config);
+        let encoding = AudioEncoding::from_i32(audio_config.audio_encoding)
+            .unwrap_or(AudioEncoding::Unspecified);
+
+        let parts = split::split(input.into(), MAX_INPUT_BYTES)?;
+        let audio = futures::stream::iter(parts)
+            .map(|part| self.synthesize(part, Some(audio_config.clone()), voice_params.clone()))
+            .buffered(parallelism.max(1))
+            .try_collect()
+            .await?;
+
+        concat::concat(encoding, audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(name: &str, language_codes: &[&str], gender: SsmlVoiceGender) -> Voice {
+        Voice {
+            name: name.to_owned(),
+            language_codes: language_codes.iter().map(|code| code.to_string()).collect(),
+            gender,
+            natural_sample_rate_hertz: 24000,
+        }
+    }
+
+    fn voices() -> Vec<Voice> {
+        use SsmlVoiceGender::*;
+        vec![
+            voice("ru-RU-Wavenet-D", &["ru-RU"], Male),
+            voice("ru-RU-Standard-A", &["ru-RU"], Female),
+            voice("ru-RU-Wavenet-C", &["ru-RU"], Female),
+            voice("en-US-Neural2-A", &["en-US"], Male),
+            voice("en-GB-News-G", &["en-GB"], Female),
+            voice("ruby-Custom", &["ruby"], Neutral),
+        ]
+    }
+
+    fn selected(
+        language: &str,
+        gender: Option<SsmlVoiceGender>,
+        voice_type: Option<VoiceType>,
+    ) -> Option<String> {
+        let voices = voices();
+        select_voice(&voices, language, gender, voice_type).map(|voice| voice.name.clone())
+    }
+
+    #[test]
+    fn voice_type_from_name() {
+        let types: Vec<VoiceType> = voices().iter().map(Voice::voice_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                VoiceType::Wavenet,
+                VoiceType::Standard,
+                VoiceType::Wavenet,
+                VoiceType::Neural2,
+                VoiceType::News,
+                VoiceType::Other(String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_by_language() {
+        // first in name order
+        assert_eq!(
+            selected("ru-RU", None, None).as_deref(),
+            Some("ru-RU-Standard-A")
+        );
+        // region is optional and case does not matter
+        assert_eq!(
+            selected("RU", None, None).as_deref(),
+            Some("ru-RU-Standard-A")
+        );
+        assert_eq!(selected("en", None, None).as_deref(), Some("en-GB-News-G"));
+        assert_eq!(
+            selected("en-us", None, None).as_deref(),
+            Some("en-US-Neural2-A")
+        );
+        // prefix of a language is not the language
+        assert_eq!(selected("r", None, None), None);
+        assert_eq!(selected("ruby", None, None).as_deref(), Some("ruby-Custom"));
+        assert_eq!(selected("de", None, None), None);
+    }
+
+    #[test]
+    fn select_by_gender_and_type() {
+        use SsmlVoiceGender::*;
+        assert_eq!(
+            selected("ru", Some(Male), None).as_deref(),
+            Some("ru-RU-Wavenet-D")
+        );
+        assert_eq!(
+            selected("ru", None, Some(VoiceType::Wavenet)).as_deref(),
+            Some("ru-RU-Wavenet-C")
+        );
+        assert_eq!(
+            selected("ru", Some(Male), Some(VoiceType::Wavenet)).as_deref(),
+            Some("ru-RU-Wavenet-D")
+        );
+        assert_eq!(selected("ru", Some(Male), Some(VoiceType::Standard)), None);
+        assert_eq!(selected("en", Some(Female), Some(VoiceType::Neural2)), None);
+    }
+
+    #[test]
+    fn cached_voices_expire() {
+        let received = Instant::now();
+        let mut cache = HashMap::new();
+        cache.insert("ru".to_owned(), (received, voices()));
+
+        assert_eq!(fresh_voices(&cache, "ru", received), Some(voices()));
+        let almost = received + VOICES_CACHE_TTL - Duration::from_secs(1);
+        assert!(fresh_voices(&cache, "ru", almost).is_some());
+        assert_eq!(
+            fresh_voices(&cache, "ru", received + VOICES_CACHE_TTL),
+            None
+        );
+        // cached per requested language
+        assert_eq!(fresh_voices(&cache, "", received), None);
+    }
+}
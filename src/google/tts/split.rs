@@ -0,0 +1,388 @@
+use super::SynthesisInput;
+
+/// Elements whose content is pronounced as a whole,
+/// inputs are never split inside them.
+const ATOMIC_ELEMENTS: &[&str] = &["audio", "say-as", "sub", "phoneme", "par", "seq", "media"];
+
+/// Splits input into parts no longer than `max_bytes` each.
+///
+/// Text is split between sentences, or between words when a sentence
+/// is too long. SSML is split the same way outside of tags, elements
+/// open at a split point are closed and reopened in the next part.
+pub(crate) fn split(input: SynthesisInput, max_bytes: usize) -> crate::Result<Vec<SynthesisInput>> {
+    match input {
+        SynthesisInput::Text(text) if text.len() > max_bytes => Ok(split_text(&text, max_bytes)
+            .into_iter()
+            .map(SynthesisInput::Text)
+            .collect()),
+        SynthesisInput::Ssml(ssml) if ssml.len() > max_bytes => Ok(split_ssml(&ssml, max_bytes)?
+            .into_iter()
+            .map(SynthesisInput::Ssml)
+            .collect()),
+        input => Ok(vec![input]),
+    }
+}
+
+fn split_text(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    for sentence in sentences(text) {
+        for piece in words(sentence, max_bytes, true) {
+            if chunk.len() + piece.len() > max_bytes {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            chunk.push_str(piece);
+        }
+    }
+    chunks.push(chunk);
+
+    chunks
+        .into_iter()
+        .map(|chunk| chunk.trim().to_owned())
+        .filter(|chunk| !chunk.is_empty())
+        .collect()
+}
+
+/// Element open at a split point.
+#[derive(Clone)]
+struct OpenElement<'a> {
+    name: &'a str,
+    tag: &'a str,
+}
+
+fn split_ssml(ssml: &str, max_bytes: usize) -> crate::Result<Vec<String>> {
+    let ssml = ssml.trim();
+    // xml declaration is not required by the service
+    let ssml = match ssml.strip_prefix("<?") {
+        Some(rest) => rest
+            .find("?>")
+            .map(|end| rest[end + 2..].trim_start())
+            .ok_or_else(|| invalid("unterminated xml declaration"))?,
+        None => ssml,
+    };
+    let (speak, body) = if ssml.starts_with("<speak") {
+        let open_end = ssml
+            .find('>')
+            .ok_or_else(|| invalid("unterminated speak tag"))?
+            + 1;
+        let close_start = ssml
+            .rfind("</speak>")
+            .ok_or_else(|| invalid("speak element is not closed"))?;
+        (&ssml[..open_end], &ssml[open_end..close_start])
+    } else {
+        ("<speak>", ssml)
+    };
+
+    // --------------------------------
+    // cut body into pieces between allowed split points
+    // --------------------------------
+    let mut pieces: Vec<(String, Vec<OpenElement>)> = vec![];
+    let mut piece = String::new();
+    let mut open: Vec<OpenElement> = vec![];
+    let mut position = 0;
+    while position < body.len() {
+        let rest = &body[position..];
+        if rest.starts_with('<') {
+            let length = if rest.starts_with("<!--") {
+                rest.find("-->").map(|end| end + 3)
+            } else {
+                rest.find('>').map(|end| end + 1)
+            }
+            .ok_or_else(|| invalid("unterminated tag"))?;
+            let tag = &rest[..length];
+            position += length;
+
+            if tag.starts_with("</") {
+                open.pop();
+                // closing tag stays with the content it closes,
+                // so that no part reopens an element only to close it
+                match pieces.last_mut() {
+                    Some((last, last_open)) if piece.is_empty() && is_splittable(&open) => {
+                        last.push_str(tag);
+                        *last_open = open.clone();
+                        continue;
+                    }
+                    _ => piece.push_str(tag),
+                }
+            } else if tag.ends_with("/>") || tag.starts_with("<!") || tag.starts_with("<?") {
+                piece.push_str(tag);
+            } else {
+                // opening tag stays with the content that follows
+                piece.push_str(tag);
+                let name = tag[1..tag.len() - 1]
+                    .split(|c: char| c.is_whitespace())
+                    .next()
+                    .unwrap_or_default();
+                open.push(OpenElement { name, tag });
+                continue;
+            }
+            if is_splittable(&open) {
+                pieces.push((std::mem::take(&mut piece), open.clone()));
+            }
+        } else {
+            let length = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..length];
+            position += length;
+
+            if text.trim().is_empty() {
+                // whitespace between tags is no split point
+                match pieces.last_mut() {
+                    Some((last, _)) if piece.is_empty() => last.push_str(text),
+                    _ => piece.push_str(text),
+                }
+            } else if is_splittable(&open) {
+                for sentence in sentences(text) {
+                    for word in words(sentence, max_bytes, false) {
+                        piece.push_str(word);
+                        pieces.push((std::mem::take(&mut piece), open.clone()));
+                    }
+                }
+            } else {
+                piece.push_str(text);
+            }
+        }
+    }
+    if !piece.is_empty() {
+        pieces.push((piece, open));
+    }
+
+    // --------------------------------
+    // pack pieces into documents
+    // --------------------------------
+    let document = |opened: &[OpenElement], body: &str, open: &[OpenElement]| {
+        let mut document = speak.to_owned();
+        opened
+            .iter()
+            .for_each(|element| document.push_str(element.tag));
+        document.push_str(body);
+        open.iter()
+            .rev()
+            .for_each(|element| document.push_str(&format!("</{}>", element.name)));
+        document.push_str("</speak>");
+        document
+    };
+
+    let mut chunks = vec![];
+    let mut opened: Vec<OpenElement> = vec![];
+    let mut chunk = String::new();
+    let mut chunk_open: Vec<OpenElement> = vec![];
+    for (piece, open) in pieces {
+        let mut next = chunk.clone() + &piece;
+        if !chunk.is_empty() && document(&opened, &next, &open).len() > max_bytes {
+            chunks.push(document(&opened, &chunk, &chunk_open));
+            opened = std::mem::take(&mut chunk_open);
+            next = piece;
+        }
+        if document(&opened, &next, &open).len() > max_bytes {
+            return Err(invalid(&format!(
+                "ssml contains part longer than {} bytes that can not be split",
+                max_bytes
+            )));
+        }
+        chunk = next;
+        chunk_open = open;
+    }
+    if !chunk.is_empty() {
+        chunks.push(document(&opened, &chunk, &chunk_open));
+    }
+    Ok(chunks)
+}
+
+fn is_splittable(open: &[OpenElement]) -> bool {
+    open.iter()
+        .all(|element| !ATOMIC_ELEMENTS.contains(&element.name))
+}
+
+/// Splits text after sentence terminators followed by whitespace,
+/// whitespace stays with the preceding sentence.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    // whether terminator was seen, and whether whitespace followed it
+    let mut terminated = false;
+    let mut separated = false;
+    for (index, c) in text.char_indices() {
+        if separated && !c.is_whitespace() {
+            sentences.push(&text[start..index]);
+            start = index;
+            separated = false;
+        }
+        if matches!(c, '.' | '!' | '?' | '…') {
+            terminated = true;
+        } else if c == '\n' {
+            terminated = true;
+            separated = true;
+        } else if c.is_whitespace() {
+            separated = terminated;
+        } else {
+            terminated = false;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// Splits sentence longer than `max_bytes` into words, and words into
+/// characters when `split_words` is set. Words of SSML text may hold
+/// entities, so they are kept whole and may exceed the limit.
+fn words(sentence: &str, max_bytes: usize, split_words: bool) -> Vec<&str> {
+    if sentence.len() <= max_bytes {
+        return vec![sentence];
+    }
+
+    let mut pieces = vec![];
+    for word in sentence.split_inclusive(char::is_whitespace) {
+        if word.len() <= max_bytes || !split_words {
+            pieces.push(word);
+            continue;
+        }
+        let mut start = 0;
+        for (index, c) in word.char_indices() {
+            if index + c.len_utf8() - start > max_bytes {
+                pieces.push(&word[start..index]);
+                start = index;
+            }
+        }
+        pieces.push(&word[start..]);
+    }
+    pieces
+}
+
+fn invalid(message: &str) -> crate::Error {
+    crate::Error::InvalidInput(message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every opened element is closed in the same chunk.
+    fn assert_balanced(ssml: &str) {
+        let mut open = vec![];
+        for tag in ssml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "in {}", ssml);
+            } else if !tag.ends_with('/') {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty(), "unclosed {:?} in {}", open, ssml);
+    }
+
+    /// Text of the chunks without tags, words joined by single spaces.
+    fn words_of(chunks: &[String]) -> String {
+        let mut text = String::new();
+        for chunk in chunks {
+            for piece in chunk.split('<') {
+                let piece = piece.find('>').map_or(piece, |end| &piece[end + 1..]);
+                text.push(' ');
+                text.push_str(piece);
+            }
+        }
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn short_input_is_kept() {
+        let input = SynthesisInput::text("Привет.");
+        assert_eq!(split(input.clone(), 100).unwrap(), vec![input]);
+    }
+
+    #[test]
+    fn text_split_between_sentences() {
+        let text = "Первое предложение. Второе предложение! Третье?";
+        let chunks = split_text(text, 45);
+        assert_eq!(
+            chunks,
+            vec!["Первое предложение.", "Второе предложение!", "Третье?"]
+        );
+    }
+
+    #[test]
+    fn text_limit_counts_bytes() {
+        // every cyrillic letter takes two bytes
+        let text = "слово ".repeat(40);
+        let chunks = split_text(&text, 50);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 50));
+        assert!(chunks.iter().any(|chunk| chunk.chars().count() > 20));
+        assert_eq!(chunks.join(" "), text.trim());
+    }
+
+    #[test]
+    fn long_word_split_on_char_boundaries() {
+        let word = "ёж".repeat(30);
+        let chunks = split_text(&word, 7);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 7));
+        assert_eq!(chunks[0], "ёжё");
+        assert_eq!(chunks.concat(), word);
+    }
+
+    #[test]
+    fn ssml_elements_reopened() {
+        let sentence = "Это довольно длинное предложение. ";
+        let ssml = format!(
+            "<speak version=\"1.1\"><p><s>{}</s></p></speak>",
+            sentence.repeat(6)
+        );
+        let chunks = split_ssml(&ssml, 200).unwrap();
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.len() <= 200);
+            assert!(chunk.starts_with("<speak version=\"1.1\"><p><s>"));
+            assert!(chunk.ends_with("</s></p></speak>"));
+            assert_balanced(chunk);
+        }
+        assert_eq!(words_of(&chunks), sentence.repeat(6).trim());
+    }
+
+    #[test]
+    fn ssml_without_empty_elements() {
+        let paragraph = format!("<p>{}</p>", "Абзац из нескольких слов. ".repeat(4));
+        let ssml = format!(
+            "<speak>{}<break time=\"1s\"/>\n{}<break/>{}</speak>",
+            paragraph, paragraph, paragraph
+        );
+        for max_bytes in (200..600).step_by(7) {
+            let chunks = split_ssml(&ssml, max_bytes).unwrap();
+            for chunk in &chunks {
+                assert!(chunk.len() <= max_bytes);
+                assert_balanced(chunk);
+                let compact = chunk.split_whitespace().collect::<String>();
+                assert!(!compact.contains("<p></p>"), "empty element in {}", chunk);
+            }
+            assert_eq!(words_of(&chunks), words_of(std::slice::from_ref(&ssml)));
+        }
+    }
+
+    #[test]
+    fn ssml_atomic_elements_kept_whole() {
+        let ssml = format!(
+            "<speak>{}<say-as interpret-as=\"cardinal\">{}</say-as> конец.</speak>",
+            "Слово. ".repeat(10),
+            "1234 ".repeat(5)
+        );
+        let chunks = split_ssml(&ssml, 100).unwrap();
+        let number = chunks
+            .iter()
+            .filter(|chunk| chunk.contains("say-as"))
+            .collect::<Vec<_>>();
+        assert_eq!(number.len(), 1);
+        assert!(number[0].contains(&"1234 ".repeat(5)));
+
+        let error = split_ssml(&ssml, 60);
+        assert!(matches!(error, Err(crate::Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn ssml_declaration_and_fragment() {
+        let ssml = format!("<?xml version=\"1.0\"?>{}", "Раз. Два. ".repeat(10));
+        let chunks = split_ssml(&ssml, 50).unwrap();
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.starts_with("<speak>") && chunk.len() <= 50));
+        assert_eq!(words_of(&chunks), "Раз. Два. ".repeat(10).trim());
+    }
+}
@@ -0,0 +1,300 @@
+use super::SynthesisInput;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Builder of SSML documents, text and attribute values are escaped.
+///
+/// https://cloud.google.com/text-to-speech/docs/ssml
+#[derive(Debug, Clone, Default)]
+pub struct SsmlBuilder {
+    body: String,
+}
+
+/// Strength of a pause between words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakStrength {
+    None,
+    XWeak,
+    Weak,
+    Medium,
+    Strong,
+    XStrong,
+}
+
+/// How the text of `<say-as>` is to be pronounced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SayAs {
+    Cardinal,
+    Ordinal,
+    Characters,
+    Fraction,
+    Unit,
+    Verbatim,
+    Telephone,
+    Expletive,
+    /// Date in the given format, e.g. `dmy`.
+    Date(String),
+    /// Time in the given format, e.g. `hms24`.
+    Time(String),
+}
+
+/// Level of `<emphasis>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emphasis {
+    Strong,
+    Moderate,
+    None,
+    Reduced,
+}
+
+/// Attributes of `<prosody>`, unset ones are omitted.
+///
+/// Values are passed as is, e.g. `slow`, `80%`, `+2st` or `-6dB`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prosody {
+    pub rate: Option<String>,
+    pub pitch: Option<String>,
+    pub volume: Option<String>,
+}
+
+impl BreakStrength {
+    fn as_str(self) -> &'static str {
+        match self {
+            BreakStrength::None => "none",
+            BreakStrength::XWeak => "x-weak",
+            BreakStrength::Weak => "weak",
+            BreakStrength::Medium => "medium",
+            BreakStrength::Strong => "strong",
+            BreakStrength::XStrong => "x-strong",
+        }
+    }
+}
+
+impl SayAs {
+    fn interpret_as(&self) -> &'static str {
+        match self {
+            SayAs::Cardinal => "cardinal",
+            SayAs::Ordinal => "ordinal",
+            SayAs::Characters => "characters",
+            SayAs::Fraction => "fraction",
+            SayAs::Unit => "unit",
+            SayAs::Verbatim => "verbatim",
+            SayAs::Telephone => "telephone",
+            SayAs::Expletive => "expletive",
+            SayAs::Date(_) => "date",
+            SayAs::Time(_) => "time",
+        }
+    }
+
+    fn format(&self) -> Option<&str> {
+        match self {
+            SayAs::Date(format) | SayAs::Time(format) => Some(format),
+            _ => None,
+        }
+    }
+}
+
+impl Emphasis {
+    fn as_str(self) -> &'static str {
+        match self {
+            Emphasis::Strong => "strong",
+            Emphasis::Moderate => "moderate",
+            Emphasis::None => "none",
+            Emphasis::Reduced => "reduced",
+        }
+    }
+}
+
+impl SsmlBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends plain text.
+    pub fn text(mut self, text: &str) -> Self {
+        escape_into(&mut self.body, text);
+        self
+    }
+
+    /// Appends a pause of the given duration.
+    pub fn pause(mut self, time: Duration) -> Self {
+        let _ = write!(self.body, r#"<break time="{}ms"/>"#, time.as_millis());
+        self
+    }
+
+    /// Appends a pause of the given strength.
+    pub fn pause_strength(mut self, strength: BreakStrength) -> Self {
+        let _ = write!(self.body, r#"<break strength="{}"/>"#, strength.as_str());
+        self
+    }
+
+    /// Appends text pronounced as the given kind of value.
+    pub fn say_as(mut self, text: &str, say_as: SayAs) -> Self {
+        self.body.push_str(r#"<say-as interpret-as=""#);
+        self.body.push_str(say_as.interpret_as());
+        if let Some(format) = say_as.format() {
+            self.body.push_str(r#"" format=""#);
+            escape_into(&mut self.body, format);
+        }
+        self.body.push_str(r#"">"#);
+        escape_into(&mut self.body, text);
+        self.body.push_str("</say-as>");
+        self
+    }
+
+    /// Appends content spoken with changed rate, pitch or volume.
+    pub fn prosody(mut self, prosody: Prosody, content: SsmlBuilder) -> Self {
+        self.body.push_str("<prosody");
+        for (name, value) in [
+            ("rate", &prosody.rate),
+            ("pitch", &prosody.pitch),
+            ("volume", &prosody.volume),
+        ]
+        .iter()
+        {
+            if let Some(value) = value {
+                let _ = write!(self.body, r#" {}=""#, name);
+                escape_into(&mut self.body, value);
+                self.body.push('"');
+            }
+        }
+        self.body.push('>');
+        self.body.push_str(&content.body);
+        self.body.push_str("</prosody>");
+        self
+    }
+
+    /// Appends emphasized content.
+    pub fn emphasis(mut self, level: Emphasis, content: SsmlBuilder) -> Self {
+        let _ = write!(
+            self.body,
+            r#"<emphasis level="{}">{}</emphasis>"#,
+            level.as_str(),
+            content.body
+        );
+        self
+    }
+
+    /// Appends audio file played in place, `fallback` is spoken
+    /// when the file can not be played.
+    pub fn audio(mut self, src: &str, fallback: SsmlBuilder) -> Self {
+        self.body.push_str(r#"<audio src=""#);
+        escape_into(&mut self.body, src);
+        self.body.push_str(r#"">"#);
+        self.body.push_str(&fallback.body);
+        self.body.push_str("</audio>");
+        self
+    }
+
+    /// Wraps the content into `<speak>` document.
+    pub fn build(self) -> SynthesisInput {
+        SynthesisInput::Ssml(format!("<speak>{}</speak>", self.body))
+    }
+}
+
+impl From<SsmlBuilder> for SynthesisInput {
+    fn from(builder: SsmlBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Escapes text for use in SSML content and attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    escape_into(&mut escaped, text);
+    escaped
+}
+
+fn escape_into(target: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => target.push_str("&amp;"),
+            '<' => target.push_str("&lt;"),
+            '>' => target.push_str("&gt;"),
+            '"' => target.push_str("&quot;"),
+            '\'' => target.push_str("&apos;"),
+            c => target.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(builder: SsmlBuilder) -> String {
+        match builder.build() {
+            SynthesisInput::Ssml(document) => document,
+            input => panic!("built {:?}", input),
+        }
+    }
+
+    #[test]
+    fn escapes_markup_characters() {
+        assert_eq!(
+            escape(r#"a & b < c > d "e" 'f'"#),
+            "a &amp; b &lt; c &gt; d &quot;e&quot; &apos;f&apos;"
+        );
+        assert_eq!(escape("plain текст"), "plain текст");
+        // already escaped text is escaped again
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn say_as_with_format() {
+        let builder = SsmlBuilder::new()
+            .text("on ")
+            .say_as("12/10", SayAs::Date(r#"dm""#.to_owned()))
+            .text(" at ")
+            .say_as("<3>", SayAs::Cardinal);
+        assert_eq!(
+            document(builder),
+            concat!(
+                "<speak>on ",
+                r#"<say-as interpret-as="date" format="dm&quot;">12/10</say-as>"#,
+                " at ",
+                r#"<say-as interpret-as="cardinal">&lt;3&gt;</say-as>"#,
+                "</speak>"
+            )
+        );
+    }
+
+    #[test]
+    fn nested_prosody_and_emphasis() {
+        let prosody = Prosody {
+            rate: Some("slow".to_owned()),
+            volume: Some(r#"+6dB" pitch="x"#.to_owned()),
+            ..Default::default()
+        };
+        let inner = SsmlBuilder::new()
+            .text("very ")
+            .emphasis(Emphasis::Strong, SsmlBuilder::new().text("loud & clear"));
+        let builder = SsmlBuilder::new()
+            .prosody(prosody, inner)
+            .pause(Duration::from_millis(250));
+        assert_eq!(
+            document(builder),
+            concat!(
+                r#"<speak><prosody rate="slow" volume="+6dB&quot; pitch=&quot;x">"#,
+                r#"very <emphasis level="strong">loud &amp; clear</emphasis>"#,
+                r#"</prosody><break time="250ms"/></speak>"#
+            )
+        );
+    }
+
+    #[test]
+    fn audio_source_escaped() {
+        let builder = SsmlBuilder::new().audio(
+            r#"https://example.com/a.wav"/><break time="10s"/><audio src="x"#,
+            SsmlBuilder::new().text("beep"),
+        );
+        assert_eq!(
+            document(builder),
+            concat!(
+                r#"<speak><audio src="https://example.com/a.wav&quot;/&gt;"#,
+                r#"&lt;break time=&quot;10s&quot;/&gt;&lt;audio src=&quot;x">"#,
+                "beep</audio></speak>"
+            )
+        );
+    }
+}
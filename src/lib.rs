@@ -1,3 +1,10 @@
+mod error;
+pub use error::{Error, Result};
+#[cfg(feature = "_rpc")]
+mod rpc;
+
+#[cfg(any(feature = "google-stt", feature = "google-tts", feature = "yandex-stt"))]
+pub mod audio;
 #[cfg(feature = "_google")]
 pub mod google;
 #[cfg(feature = "_yandex")]
@@ -0,0 +1,39 @@
+use crate::{Error, Result};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{Channel, ClientTlsConfig},
+    Interceptor, Request,
+};
+
+/// Connects to the given endpoint. Plaintext `http://` endpoints skip tls,
+/// which allows to target local emulators and mocks.
+pub(crate) async fn connect(tls_config: ClientTlsConfig, endpoint: &str) -> Result<Channel> {
+    let channel =
+        Channel::from_shared(endpoint.to_owned()).map_err(|e| Error::Transport(Box::new(e)))?;
+    let channel = if endpoint.starts_with("http://") {
+        channel
+    } else {
+        channel.tls_config(tls_config)?
+    };
+    Ok(channel.connect().await?)
+}
+
+/// Interceptor adding `authorization` header, if there is a token,
+/// and `metadata` to every request.
+///
+/// Error type of interceptors is set by tonic, and these never fail.
+#[allow(clippy::result_large_err)]
+pub(crate) fn interceptor(
+    token: Option<MetadataValue<Ascii>>,
+    metadata: Vec<(&'static str, MetadataValue<Ascii>)>,
+) -> Interceptor {
+    Interceptor::new(move |mut req: Request<()>| {
+        if let Some(token) = &token {
+            req.metadata_mut().insert("authorization", token.clone());
+        }
+        for (key, value) in &metadata {
+            req.metadata_mut().insert(*key, value.clone());
+        }
+        Ok(req)
+    })
+}
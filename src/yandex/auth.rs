@@ -1,8 +1,12 @@
+use crate::{Error, Result};
 use jsonwebtoken::{encode, EncodingKey, Header};
-use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio::sync::RwLock;
 
+const DEFAULT_HOST: &str = "https://iam.api.cloud.yandex.net";
+const TOKEN_PATH: &str = "/iam/v1/tokens";
+
 #[derive(Serialize, Deserialize)]
 struct Claims<'t> {
     iss: &'t str,
@@ -11,15 +15,6 @@ struct Claims<'t> {
     exp: u64,
 }
 
-static YANDEX_KEY: OnceCell<EncodingKey> = OnceCell::new();
-
-pub(super) async fn initialize_auth(key: &[u8]) {
-    YANDEX_KEY
-        .set(EncodingKey::from_rsa_pem(key).unwrap())
-        .unwrap();
-    get_auth_token().await;
-}
-
 #[derive(Serialize)]
 struct TokenRequestPayload {
     jwt: String,
@@ -32,56 +27,95 @@ struct TokenRequestResult {
     expires_at: chrono::DateTime<chrono::Utc>,
 }
 
-async fn get_iam_token() -> TokenRequestResult {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let hour_later = now + 3600;
-
-    let mut h = Header::new(jsonwebtoken::Algorithm::PS256);
-    h.kid = Some("aje04ppj0e85d7njj0sf".to_owned());
-
-    let claims = Claims {
-        iss: "ajede2r7i8dtgcgehtdl",
-        aud: "https://iam.api.cloud.yandex.net/iam/v1/tokens",
-        iat: now,
-        exp: hour_later,
-    };
-    let token = encode(&h, &claims, &YANDEX_KEY.get().unwrap()).unwrap();
-
-    let result = reqwest::Client::new()
-        .post(
-            "https://iam.api.cloud.yandex.net/iam/v1/tokens"
-                .parse::<reqwest::Url>()
-                .unwrap(),
-        )
-        .json(&TokenRequestPayload { jwt: token })
-        .send()
-        .await
-        .unwrap();
+/// IAM tokens of a single service account key.
+///
+/// Clones share the token cache.
+#[derive(Clone)]
+pub(crate) struct Auth {
+    inner: Arc<AuthInner>,
+}
 
-    result.json::<TokenRequestResult>().await.unwrap()
+struct AuthInner {
+    key: EncodingKey,
+    service_account_id: String,
+    key_id: String,
+    /// Tokens are requested here, while the audience of the jwt
+    /// stays the url of the real api.
+    token_url: String,
+    client: reqwest::Client,
+    token: RwLock<Option<TokenRequestResult>>,
 }
 
-static TOKEN: OnceCell<RwLock<TokenRequestResult>> = OnceCell::new();
+impl Auth {
+    pub(crate) fn new(
+        key: EncodingKey,
+        service_account_id: &str,
+        key_id: &str,
+        endpoint: Option<&str>,
+    ) -> Auth {
+        let host = endpoint.unwrap_or(DEFAULT_HOST).trim_end_matches('/');
+        Auth {
+            inner: Arc::new(AuthInner {
+                key,
+                service_account_id: service_account_id.to_owned(),
+                key_id: key_id.to_owned(),
+                token_url: format!("{}{}", host, TOKEN_PATH),
+                client: reqwest::Client::new(),
+                token: RwLock::new(None),
+            }),
+        }
+    }
 
-pub async fn get_auth_token() -> String {
-    if let Some(res) = TOKEN.get() {
-        {
-            let lock = res.read().await;
-            if lock.expires_at - chrono::Utc::now() > chrono::Duration::zero() {
-                return lock.iam_token.clone();
+    /// Cached token, or a new one if cached has expired.
+    pub(crate) async fn token(&self) -> Result<String> {
+        if let Some(token) = &*self.inner.token.read().await {
+            if token.expires_at - chrono::Utc::now() > chrono::Duration::zero() {
+                return Ok(token.iam_token.clone());
             }
         }
-        let result = get_iam_token().await;
+        let result = self.request_token().await?;
         let token = result.iam_token.clone();
-        *res.write().await = result;
-        token
-    } else {
-        let result = get_iam_token().await;
-        let token = result.iam_token.clone();
-        let _ = TOKEN.set(RwLock::new(result));
-        token
+        *self.inner.token.write().await = Some(result);
+        Ok(token)
+    }
+
+    async fn request_token(&self) -> Result<TokenRequestResult> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let hour_later = now + 3600;
+
+        let mut h = Header::new(jsonwebtoken::Algorithm::PS256);
+        h.kid = Some(self.inner.key_id.clone());
+
+        let audience = format!("{}{}", DEFAULT_HOST, TOKEN_PATH);
+        let claims = Claims {
+            iss: &self.inner.service_account_id,
+            aud: &audience,
+            iat: now,
+            exp: hour_later,
+        };
+        let token = encode(&h, &claims, &self.inner.key)?;
+
+        let result = self
+            .inner
+            .client
+            .post(&self.inner.token_url)
+            .json(&TokenRequestPayload { jwt: token })
+            .send()
+            .await
+            .map_err(|e| Error::Auth(e.to_string()))?;
+
+        let status = result.status();
+        if !status.is_success() {
+            return Err(Error::Auth(format!(
+                "iam token request failed with status code {}: {}",
+                status,
+                result.text().await.unwrap_or_default()
+            )));
+        }
+
+        Ok(result.json::<TokenRequestResult>().await?)
     }
 }
@@ -1,48 +1,158 @@
+use jsonwebtoken::EncodingKey;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
 #[cfg(feature = "_rpc")]
-use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::transport::ClientTlsConfig;
 
 mod auth;
+#[cfg(feature = "_rpc")]
 mod generated;
 #[cfg(feature = "yandex-streaming-stt")]
 pub mod streaming_stt;
 #[cfg(feature = "yandex-stt")]
 pub mod stt;
 
-#[cfg(feature = "_rpc")]
+/// Service account and key id used unless
+/// [`RpcBuilder::service_account`] sets others.
+const DEFAULT_SERVICE_ACCOUNT_ID: &str = "ajede2r7i8dtgcgehtdl";
+const DEFAULT_KEY_ID: &str = "aje04ppj0e85d7njj0sf";
+
+/// Service whose endpoint can be overridden with [`RpcBuilder::endpoint`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Service {
+    /// IAM api tokens are requested from.
+    Iam,
+    #[cfg(feature = "yandex-stt")]
+    Stt,
+    #[cfg(feature = "yandex-streaming-stt")]
+    StreamingStt,
+}
+
 pub struct RpcBuilder {
+    #[cfg(feature = "_rpc")]
     tls_config: ClientTlsConfig,
     folder_id: String,
+    endpoints: HashMap<Service, String>,
+    key: EncodingKey,
+    service_account_id: String,
+    key_id: String,
+    /// Created on first use, so that clients of the builder share tokens.
+    auth: OnceCell<auth::Auth>,
 }
 
+#[cfg(feature = "_rpc")]
 macro_rules! initialize_fn {
-    ($name: ident, $fun_name: ident) => {
-        pub async fn $fun_name(self) -> RpcBuilder {
-            $name::initialize(self.tls_config.clone(), self.folder_id.clone()).await;
-            self
+    ($name: ident, $service: ident, $fun_name: ident) => {
+        pub async fn $fun_name(self) -> crate::Result<RpcBuilder> {
+            let endpoint = self.endpoints.get(&Service::$service).map(|x| x.as_str());
+            $name::initialize(
+                self.tls_config.clone(),
+                endpoint,
+                self.folder_id.clone(),
+                self.auth(),
+            )
+            .await?;
+            Ok(self)
         }
     };
 }
 
-#[cfg(feature = "_rpc")]
 impl RpcBuilder {
-    pub async fn new(key: &[u8], folder_id: String) -> RpcBuilder {
-        let mut tls_config = tokio_rustls::rustls::ClientConfig::new();
-        tls_config
-            .root_store
-            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-        tls_config.set_protocols(&["h2".into()]);
-        let tls_config = ClientTlsConfig::new().rustls_client_config(tls_config);
-
-        auth::initialize_auth(key).await;
+    /// Takes PEM encoded private key of a service account.
+    pub async fn new(key: &[u8], folder_id: String) -> crate::Result<RpcBuilder> {
+        #[cfg(feature = "_rpc")]
+        let tls_config = {
+            let mut tls_config = tokio_rustls::rustls::ClientConfig::new();
+            tls_config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            tls_config.set_protocols(&["h2".into()]);
+            ClientTlsConfig::new().rustls_client_config(tls_config)
+        };
 
-        RpcBuilder {
+        Ok(RpcBuilder {
+            #[cfg(feature = "_rpc")]
             tls_config,
             folder_id,
+            endpoints: HashMap::new(),
+            key: EncodingKey::from_rsa_pem(key)?,
+            service_account_id: DEFAULT_SERVICE_ACCOUNT_ID.to_owned(),
+            key_id: DEFAULT_KEY_ID.to_owned(),
+            auth: OnceCell::new(),
+        })
+    }
+
+    /// Sets the service account the key belongs to and the id of the key,
+    /// both are put into token requests.
+    pub fn service_account(
+        mut self,
+        service_account_id: impl Into<String>,
+        key_id: impl Into<String>,
+    ) -> RpcBuilder {
+        self.service_account_id = service_account_id.into();
+        self.key_id = key_id.into();
+        self.auth = OnceCell::new();
+        self
+    }
+
+    /// Overrides the endpoint of the given service, for example to target
+    /// a local mock. Endpoints starting with `http://` are connected
+    /// to without tls.
+    pub fn endpoint(mut self, service: Service, endpoint: impl Into<String>) -> RpcBuilder {
+        if service == Service::Iam {
+            self.auth = OnceCell::new();
         }
+        self.endpoints.insert(service, endpoint.into());
+        self
+    }
+
+    fn auth(&self) -> auth::Auth {
+        self.auth
+            .get_or_init(|| {
+                auth::Auth::new(
+                    self.key.clone(),
+                    &self.service_account_id,
+                    &self.key_id,
+                    self.endpoints.get(&Service::Iam).map(|x| x.as_str()),
+                )
+            })
+            .clone()
+    }
+
+    #[cfg(feature = "yandex-stt")]
+    pub async fn initialize_stt(self) -> crate::Result<RpcBuilder> {
+        let endpoint = self.endpoints.get(&Service::Stt).map(|x| x.as_str());
+        stt::initialize(endpoint, self.auth()).await?;
+        Ok(self)
+    }
+
+    /// Creates a client for short audio recognition. Clients created
+    /// by the same builder share IAM tokens.
+    #[cfg(feature = "yandex-stt")]
+    pub async fn stt_client(&self) -> crate::Result<stt::SttClient> {
+        let endpoint = self.endpoints.get(&Service::Stt).map(|x| x.as_str());
+        stt::SttClient::new(endpoint, self.auth()).await
     }
 
     #[cfg(feature = "yandex-streaming-stt")]
-    initialize_fn!(streaming_stt, initialize_streaming_stt);
+    initialize_fn!(streaming_stt, StreamingStt, initialize_streaming_stt);
+
+    /// Connects a streaming recognition client for the folder of the builder,
+    /// other folders or service accounts need builders of their own.
+    #[cfg(feature = "yandex-streaming-stt")]
+    pub async fn streaming_stt_client(&self) -> crate::Result<streaming_stt::StreamingSttClient> {
+        let endpoint = self
+            .endpoints
+            .get(&Service::StreamingStt)
+            .map(|x| x.as_str());
+        streaming_stt::StreamingSttClient::new(
+            self.tls_config.clone(),
+            endpoint,
+            self.folder_id.clone(),
+            self.auth(),
+        )
+        .await
+    }
     // #[cfg(feature = "google-tts")]
     // initialize_fn!(tts, initialize_tts);
     // #[cfg(feature = "google-tasks")]
@@ -50,9 +160,3 @@ impl RpcBuilder {
     // #[cfg(feature = "google-logging")]
     // initialize_fn!(logging, initialize_logging);
 }
-
-#[cfg(feature = "_rpc")]
-struct Service {
-    channel: Channel,
-    folder_id: String,
-}
@@ -1,35 +1,57 @@
+use super::auth::Auth;
 use crate::yandex::generated::yandex::cloud::ai::stt::v2;
 
-use super::Service;
 use once_cell::sync::OnceCell;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tonic::{
     metadata::MetadataValue,
     transport::{Channel, ClientTlsConfig},
-    Request,
 };
 pub use v2::{RecognitionConfig, RecognitionSpec, StreamingRecognitionResponse};
 
 const DEFAULT_HOST: &str = concat!("https://", "stt.api.cloud", ".yandex.net");
 
-static SERVICE: OnceCell<Service> = OnceCell::new();
-
-pub(crate) async fn initialize<'a>(tls_config: ClientTlsConfig, folder_id: String) {
-    let inner = Service {
-        channel: Channel::from_shared(DEFAULT_HOST)
-            .unwrap()
-            .tls_config(tls_config)
-            .unwrap()
-            .connect()
-            .await
-            .unwrap(),
-        folder_id,
-    };
-    if SERVICE.set(inner).is_err() {
-        panic!("Already initialized stt.api.cloud service");
+/// Handle to the `stt.api.cloud` service.
+///
+/// Clones share the underlying channel, so cloning is cheap.
+#[derive(Clone)]
+pub struct StreamingSttClient {
+    channel: Channel,
+    folder_id: String,
+    auth: Auth,
+}
+
+impl StreamingSttClient {
+    pub(crate) async fn new(
+        tls_config: ClientTlsConfig,
+        endpoint: Option<&str>,
+        folder_id: String,
+        auth: Auth,
+    ) -> crate::Result<StreamingSttClient> {
+        // token is requested in advance, so that errors are visible on start
+        auth.token().await?;
+        Ok(StreamingSttClient {
+            channel: crate::rpc::connect(tls_config, endpoint.unwrap_or(DEFAULT_HOST)).await?,
+            folder_id,
+            auth,
+        })
     }
 }
 
+static SERVICE: OnceCell<StreamingSttClient> = OnceCell::new();
+
+pub(crate) async fn initialize(
+    tls_config: ClientTlsConfig,
+    endpoint: Option<&str>,
+    folder_id: String,
+    auth: Auth,
+) -> crate::Result<()> {
+    let client = StreamingSttClient::new(tls_config, endpoint, folder_id, auth).await?;
+    SERVICE
+        .set(client)
+        .map_err(|_| crate::Error::AlreadyInitialized("stt.api.cloud"))
+}
+
 fn default_config(folder_id: String) -> v2::RecognitionConfig {
     v2::RecognitionConfig {
         specification: Some(v2::RecognitionSpec {
@@ -49,58 +71,83 @@ fn default_config(folder_id: String) -> v2::RecognitionConfig {
 
 pub async fn streaming_recognize(
     config: Option<v2::RecognitionConfig>,
-) -> (
+) -> crate::Result<(
     UnboundedSender<Vec<u8>>,
-    UnboundedReceiver<StreamingRecognitionResponse>,
-) {
-    let stt = SERVICE.get().unwrap();
-    let config = config.unwrap_or_else(|| default_config(stt.folder_id.to_string()));
-
-    // --------------------------------
-    // retrieve token and construct channel
-    // --------------------------------
-    let channel = stt.channel.clone();
-    let token = super::auth::get_auth_token().await;
-    let bearer_token = format!("Bearer {}", token.as_str());
-    let token = MetadataValue::from_str(&bearer_token).unwrap();
-
-    let mut service = v2::stt_service_client::SttServiceClient::with_interceptor(
-        channel,
-        move |mut req: Request<()>| {
-            let token = token.clone();
-            req.metadata_mut().insert("authorization", token);
-            Ok(req)
-        },
-    );
-
-    let (audio_sender, mut audio_receiver) = tokio::sync::mpsc::unbounded_channel();
-
-    let stream = async_stream::stream! {
-        // config first
-        yield v2::StreamingRecognitionRequest {
-            streaming_request: Some(v2::streaming_recognition_request::StreamingRequest::Config(
-                config,
-            )),
-        };
+    UnboundedReceiver<crate::Result<StreamingRecognitionResponse>>,
+)> {
+    SERVICE
+        .get()
+        .ok_or(crate::Error::NotInitialized("stt.api.cloud"))?
+        .streaming_recognize(config)
+        .await
+}
 
-        while let Some(audio) = audio_receiver.recv().await {
+impl StreamingSttClient {
+    pub async fn streaming_recognize(
+        &self,
+        config: Option<v2::RecognitionConfig>,
+    ) -> crate::Result<(
+        UnboundedSender<Vec<u8>>,
+        UnboundedReceiver<crate::Result<StreamingRecognitionResponse>>,
+    )> {
+        let config = config.unwrap_or_else(|| default_config(self.folder_id.to_string()));
+
+        // --------------------------------
+        // retrieve token and construct channel
+        // --------------------------------
+        let channel = self.channel.clone();
+        let token = self.auth.token().await?;
+        let bearer_token = format!("Bearer {}", token.as_str());
+        let token = MetadataValue::from_str(&bearer_token)?;
+
+        let mut service = v2::stt_service_client::SttServiceClient::with_interceptor(
+            channel,
+            crate::rpc::interceptor(Some(token), vec![]),
+        );
+
+        let (audio_sender, mut audio_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let stream = async_stream::stream! {
+            // config first
             yield v2::StreamingRecognitionRequest {
-                streaming_request: Some(
-                    v2::streaming_recognition_request::StreamingRequest::AudioContent(audio),
-                ),
+                streaming_request: Some(v2::streaming_recognition_request::StreamingRequest::Config(
+                    config,
+                )),
             };
-        }
-    };
 
-    let (result_sender, result_receiver) = tokio::sync::mpsc::unbounded_channel();
+            while let Some(audio) = audio_receiver.recv().await {
+                yield v2::StreamingRecognitionRequest {
+                    streaming_request: Some(
+                        v2::streaming_recognition_request::StreamingRequest::AudioContent(audio),
+                    ),
+                };
+            }
+        };
+
+        let (result_sender, result_receiver) = tokio::sync::mpsc::unbounded_channel();
 
-    tokio::spawn(async move {
-        let messages = service.streaming_recognize(stream).await.unwrap();
-        let mut inner = messages.into_inner();
-        while let Some(message) = inner.message().await.unwrap() {
-            result_sender.send(message).unwrap();
-        }
-    });
+        tokio::spawn(async move {
+            let mut inner = match service.streaming_recognize(stream).await {
+                Ok(messages) => messages.into_inner(),
+                Err(status) => {
+                    let _ = result_sender.send(Err(status.into()));
+                    return;
+                }
+            };
+            loop {
+                let message = match inner.message().await {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => break,
+                    Err(status) => Err(status.into()),
+                };
+                let is_err = message.is_err();
+                // receiver was dropped, nobody is interested in results anymore
+                if result_sender.send(message).is_err() || is_err {
+                    break;
+                }
+            }
+        });
 
-    (audio_sender, result_receiver)
+        Ok((audio_sender, result_receiver))
+    }
 }
@@ -1,29 +1,113 @@
-use once_cell::sync::{Lazy, OnceCell};
+use super::auth::Auth;
+use crate::{Error, Result};
+use once_cell::sync::OnceCell;
 use reqwest::Client;
 
-static YANDEX_SHORT_STT_URL: Lazy<reqwest::Url> = Lazy::new(|| {
-    reqwest::Url::parse("https://stt.api.cloud.yandex.net/speech/v1/stt:recognize?topic=general:rc&format=lpcm&sampleRateHertz=8000").unwrap()
-});
-static CLIENT: OnceCell<Client> = OnceCell::new();
+const DEFAULT_HOST: &str = "https://stt.api.cloud.yandex.net";
+const RECOGNIZE_PATH: &str = "/speech/v1/stt:recognize?topic=general:rc&format=lpcm";
 
-pub async fn recognize(audio: Vec<u8>) -> Option<String> {
-    let client = CLIENT.get_or_init(Client::new);
+/// Handle to the short audio recognition api.
+///
+/// Clones share the underlying http client and token cache,
+/// so cloning is cheap.
+#[derive(Clone)]
+pub struct SttClient {
+    client: Client,
+    auth: Auth,
+    url: reqwest::Url,
+}
 
-    let result = client
-        .post(YANDEX_SHORT_STT_URL.clone())
-        .bearer_auth(super::auth::get_auth_token().await)
-        .body(audio)
-        .send()
-        .await
-        .ok()?;
+impl SttClient {
+    pub(crate) async fn new(endpoint: Option<&str>, auth: Auth) -> Result<SttClient> {
+        let host = endpoint.unwrap_or(DEFAULT_HOST).trim_end_matches('/');
+        let url = reqwest::Url::parse(&format!("{}{}", host, RECOGNIZE_PATH))
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+        // token is requested in advance, so that errors are visible on start
+        auth.token().await?;
+        Ok(SttClient {
+            client: Client::new(),
+            auth,
+            url,
+        })
+    }
+}
+
+static SERVICE: OnceCell<SttClient> = OnceCell::new();
+
+pub(crate) async fn initialize(endpoint: Option<&str>, auth: Auth) -> Result<()> {
+    let client = SttClient::new(endpoint, auth).await?;
+    SERVICE
+        .set(client)
+        .map_err(|_| Error::AlreadyInitialized("yandex stt"))
+}
+
+/// Sample rates accepted by yandex for lpcm format.
+const SUPPORTED_SAMPLE_RATES: &[u32] = &[8000, 16000, 48000];
 
-    let recognized = result
-        .json::<serde_json::Value>()
+/// Recognizes 16 bit mono PCM audio. Sample rate is taken from WAV header
+/// if present, headerless audio is expected to be sampled at 8000 Hz.
+pub async fn recognize(audio: Vec<u8>) -> Result<String> {
+    SERVICE
+        .get()
+        .ok_or(Error::NotInitialized("yandex stt"))?
+        .recognize(audio)
         .await
-        .ok()?
-        .get("result")?
-        .as_str()?
-        .to_owned();
+}
+
+impl SttClient {
+    pub async fn recognize(&self, audio: Vec<u8>) -> Result<String> {
+        let pcm = crate::audio::detect_pcm(&audio)?;
+        let sample_rate = match pcm.format {
+            Some(format) => {
+                if format.channels != 1 {
+                    return Err(Error::UnsupportedAudio(format!(
+                        "{} channel audio is not supported, expected mono",
+                        format.channels
+                    )));
+                }
+                if !SUPPORTED_SAMPLE_RATES.contains(&format.sample_rate) {
+                    return Err(Error::UnsupportedAudio(format!(
+                        "sample rate {} Hz is not supported, expected one of {:?}",
+                        format.sample_rate, SUPPORTED_SAMPLE_RATES
+                    )));
+                }
+                format.sample_rate
+            }
+            None => 8000,
+        };
+        let body = match pcm.format {
+            Some(_) => pcm.data.to_vec(),
+            None => audio,
+        };
+
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("sampleRateHertz", &sample_rate.to_string());
+
+        let result = self
+            .client
+            .post(url)
+            .bearer_auth(self.auth.token().await?)
+            .body(body)
+            .send()
+            .await?;
+
+        let status = result.status();
+        if !status.is_success() {
+            return Err(Error::Http {
+                status: status.as_u16(),
+                body: result.text().await.unwrap_or_default(),
+            });
+        }
+
+        let recognized = result
+            .json::<serde_json::Value>()
+            .await?
+            .get("result")
+            .and_then(|x| x.as_str())
+            .ok_or_else(|| Error::Decode("missing `result` field".to_owned()))?
+            .to_owned();
 
-    Some(recognized)
+        Ok(recognized)
+    }
 }